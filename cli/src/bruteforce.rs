@@ -5,6 +5,7 @@ use rl::model::RandomModel;
 use rl::pool::Pool;
 use runtime::backtest::BasicBacktest;
 use runtime::runtime::Runtime;
+use std::time::Instant;
 use tch::Device;
 
 // pub struct BruteforceSearch {
@@ -26,6 +27,7 @@ pub fn brute_force(
     network: Network,
     action_space: rl::action::ActionSpace,
     use_cuda: bool,
+    deadline: Option<Instant>, // checked between iterations, the pool so far is returned
 ) -> Pool {
     let device = if use_cuda {
         Device::cuda_if_available()
@@ -52,6 +54,10 @@ pub fn brute_force(
     let num_iterations = 2000;
 
     for iteration in 0..num_iterations {
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            println!("--- Time budget reached after {} iterations ---", iteration);
+            break;
+        }
         println!("--- Iteration {} ---", iteration);
         let trajectories = env.sample(&mut runtime, &mut model, &device);
 
@@ -61,7 +67,9 @@ pub fn brute_force(
             ep_lengths.push(traj.len());
             if let Some(last_step) = traj.last() {
                 if last_step.action == Action::Done {
-                    env.pool.insert(&mut runtime, machine.callgraph.clone());
+                    let path: Vec<Action> = traj.iter().map(|step| step.action.clone()).collect();
+                    env.pool
+                        .insert(&mut runtime, machine.callgraph.clone(), &path);
                 }
             }
         });
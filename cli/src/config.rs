@@ -0,0 +1,100 @@
+// Project defaults read from comet.toml next to where comet is run, e.g.
+//
+// entry = "examples/behavior_2.cm"
+// top = 10
+// rank = "ir"
+// emit = "cir"
+//
+// Command line flags override these.
+use crate::{Emit, Rank};
+use clap::ValueEnum;
+use std::path::Path;
+use toml_edit::DocumentMut;
+
+pub const CONFIG_FILE: &str = "comet.toml";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub entry: Option<String>, // file used when --file is not given
+    pub top: Option<usize>,
+    pub rank: Option<Rank>,
+    pub emit: Option<Emit>,
+}
+
+// A setting taking one of the values of the matching command line flag.
+fn value<T: ValueEnum>(key: &str, s: &str) -> Result<T, String> {
+    T::from_str(s, false).map_err(|_| format!("{}: unknown {} {}", CONFIG_FILE, key, s))
+}
+
+impl Config {
+    pub fn parse(src: &str) -> Result<Config, String> {
+        let doc: DocumentMut = src.parse().map_err(|e| format!("{}: {}", CONFIG_FILE, e))?;
+        let mut config = Config::default();
+        for (key, item) in doc.iter() {
+            let string = || {
+                item.as_str()
+                    .map(|s| s.to_string())
+                    .ok_or(format!("{}: {} must be a string", CONFIG_FILE, key))
+            };
+            match key {
+                "entry" => config.entry = Some(string()?),
+                "rank" => config.rank = Some(value(key, &string()?)?),
+                "emit" => config.emit = Some(value(key, &string()?)?),
+                "top" => {
+                    let top = item
+                        .as_integer()
+                        .and_then(|n| usize::try_from(n).ok())
+                        .ok_or(format!(
+                            "{}: top must be a non-negative integer",
+                            CONFIG_FILE
+                        ))?;
+                    config.top = Some(top);
+                }
+                other => return Err(format!("{}: unknown setting {}", CONFIG_FILE, other)),
+            }
+        }
+        Ok(config)
+    }
+
+    // The configuration in dir, or the defaults when there is none.
+    pub fn load(dir: &Path) -> Result<Config, String> {
+        let path = dir.join(CONFIG_FILE);
+        if !path.is_file() {
+            return Ok(Config::default());
+        }
+        let src = std::fs::read_to_string(&path).map_err(|e| format!("{:?}: {}", path, e))?;
+        Config::parse(&src)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse("entry = \"main.cm\"\ntop = 5\nrank = \"ir\"\n").unwrap();
+        assert_eq!(
+            config,
+            Config {
+                entry: Some("main.cm".to_string()),
+                top: Some(5),
+                rank: Some(Rank::Ir),
+                emit: None,
+            }
+        );
+        assert!(Config::parse("top = -1").is_err());
+        assert!(Config::parse("top = \"5\"").is_err());
+        assert!(Config::parse("max_depth = 3").is_err());
+        assert_eq!(
+            Config::parse("emit = \"cir\"").unwrap().emit,
+            Some(Emit::Cir)
+        );
+        assert!(Config::parse("emit = \"json\"").is_err());
+        assert!(Config::parse("rank = \"sharpe\"").is_err());
+        assert_eq!(
+            Config::load(Path::new("/nonexistent")).unwrap(),
+            Config::default()
+        );
+    }
+}
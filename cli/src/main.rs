@@ -1,26 +1,292 @@
 pub mod bruteforce;
+mod config;
 pub mod transformer;
+mod watch;
 mod weights;
-use clap::Parser;
-use parser::ast::NodeType;
-use parser::behavior::BehaviorDecl;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, ValueEnum};
+use config::Config;
+use parser::ast::{Network, NodeType};
+use parser::behavior::{BehaviorDecl, InputCode};
+use parser::parser::{Analysis, AnalyzeOptions, ParserError};
+use parser::span::Diagnostic;
 use rl::action::ActionSpace;
+use rl::pool::Pool;
 use runtime::runtime::Runtime;
-use std::fs;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::Path;
+use std::time::{Duration, Instant};
 use tch::Device;
 use weights::{load, save};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    /// The .cm file, or - to read it from stdin. Imports from stdin resolve from the working directory
     #[arg(short, long)]
-    file: String,
+    file: Option<String>,
     #[arg(short, long)]
     cuda: bool,
+    /// Print only the k simplest expressions found
+    #[arg(long)]
+    top: Option<usize>,
+    /// Order the expressions found by a structural score instead
+    #[arg(long, value_enum)]
+    rank: Option<Rank>,
+    /// Print a random sample of n expressions found, reproducible with --seed
+    #[arg(long, conflicts_with_all = ["top", "rank"])]
+    sample: Option<usize>,
+    /// Seed of the random sample taken by --sample
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Print the parsed network in the given format instead of searching
+    #[arg(long, value_enum, conflicts_with_all = ["watch", "dump_symbols", "list_ops"])]
+    emit: Option<Emit>,
+    /// Re-parse the file whenever it or one of its imports is saved
+    #[arg(long)]
+    watch: bool,
+    /// Print the behaviors and flows of the file and its imports, then exit
+    #[arg(long)]
+    dump_symbols: bool,
+    /// When no expression is found, print why the behaviors could not be searched
+    #[arg(long)]
+    explain_empty: bool,
+    /// Fail when the analysis reports any warning
+    #[arg(long)]
+    deny_warnings: bool,
+    /// Stop searching after this many seconds and print what was found
+    #[arg(long)]
+    timeout: Option<u64>,
+    /// Print the available operators with their argument and output types, then exit
+    #[arg(long)]
+    list_ops: bool,
+    /// Enable a feature, loading the imports guarded by it (Import "x.cm" If name). Repeatable
+    #[arg(long = "feature")]
+    features: Vec<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Rank {
+    /// Estimated information ratio, from the operators an expression uses
+    Ir,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Emit {
+    /// The versioned JSON form of the network
+    Cir,
+}
+
+impl Args {
+    // Settings not given on the command line are taken from comet.toml.
+    fn with_config(mut self, config: Config) -> Args {
+        self.file = self.file.or(config.entry);
+        self.top = self.top.or(config.top);
+        self.rank = self.rank.or(config.rank);
+        self.emit = self.emit.or(config.emit);
+        self
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.timeout
+            .map(|secs| Instant::now() + Duration::from_secs(secs))
+    }
+
+    // Checked by validate, except for --list-ops, which reads no file.
+    fn file(&self) -> &str {
+        self.file
+            .as_deref()
+            .expect("No --file given and no entry in comet.toml")
+    }
+
+    // Checks clap cannot do on its own, because the file may come from comet.toml.
+    fn validate(&self) -> Result<(), clap::Error> {
+        if self.list_ops {
+            return Ok(());
+        }
+        match self.file.as_deref() {
+            None => Err(Args::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "No --file given and no entry in comet.toml",
+            )),
+            Some("-") if self.watch => Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                "--watch needs a file, not stdin",
+            )),
+            _ => Ok(()),
+        }
+    }
 }
 
 fn main() {
-    _main(Args::parse());
+    // Parsed first, so that --help and flag errors do not depend on a valid comet.toml.
+    let args = Args::parse();
+    let config = Config::load(Path::new(".")).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    });
+    let args = args.with_config(config);
+    args.validate().unwrap_or_else(|e| e.exit());
+    if args.list_ops {
+        println!("{}", parser::behavior::operator_table());
+        return;
+    }
+    if args.dump_symbols {
+        let code = read_source(&args)
+            .and_then(|src| load_code(&args, &src))
+            .unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            });
+        println!("{}", parser::behavior::symbol_table(&code));
+        return;
+    }
+    if args.watch {
+        let path = Path::new(args.file());
+        let features: HashSet<String> = args.features.iter().cloned().collect();
+        watch::watch(path, &features, || {
+            let src = read_source(&args).unwrap_or_default();
+            match check(&args, &src) {
+                Ok(analysis) => println!(
+                    "--- {} nodes, {} behaviors to search ---\n{}",
+                    analysis.network.nodes.len(),
+                    analysis.behavior_nodes.len(),
+                    analysis.network
+                ),
+                Err(d) => println!("--- Failed to parse {:?} ---\n{}", path, d.render(&src)),
+            }
+        });
+        return;
+    }
+    _main(args);
+}
+
+// The expressions to print, in the order and number the flags ask for.
+fn found_exprs(pool: &Pool, args: &Args) -> Vec<String> {
+    if let Some(n) = args.sample {
+        return pool.sampled_exprs(n, args.seed);
+    }
+    match args.rank {
+        Some(Rank::Ir) => {
+            let mut exprs = pool.ranked_exprs(rl::pool::ir_proxy);
+            if let Some(k) = args.top {
+                exprs.truncate(k);
+            }
+            exprs
+        }
+        None => match args.top {
+            Some(k) => pool.simplest_exprs(k),
+            None => pool.exprs(),
+        },
+    }
+}
+
+// The source of the file, or of stdin for "-", which the spans of errors point into. A file that
+// cannot be read gives an empty source, and loading it reports why.
+fn read_source(args: &Args) -> Result<String, ParserError> {
+    if args.file() == "-" {
+        let mut src = String::new();
+        std::io::stdin()
+            .read_to_string(&mut src)
+            .map_err(|e| ParserError::SemanticError(format!("Failed to read source: {}", e)))?;
+        Ok(src)
+    } else {
+        Ok(std::fs::read_to_string(args.file()).unwrap_or_default())
+    }
+}
+
+// Prints the expressions found. With --explain-empty, an empty result is followed by why the
+// behavior could not be searched, in every search mode.
+fn print_found(pool: &Pool, args: &Args, empty_reasons: &[String]) {
+    println!("--- Expressions found ---");
+    let exprs = found_exprs(pool, args);
+    if pool.collapsed() > 0 {
+        println!(
+            "--- {} reordered duplicates collapsed ---",
+            pool.collapsed()
+        );
+    }
+    if exprs.is_empty() && args.explain_empty {
+        println!("--- No expressions found ---");
+        if empty_reasons.is_empty() {
+            println!("Every operator is usable; all candidates were rejected by the pool");
+        }
+        for reason in empty_reasons {
+            println!("{}", reason);
+        }
+    }
+    for expr in exprs {
+        println!("{}", expr);
+    }
+}
+
+// Parses the file, or the source read from stdin for "-", with the imports of the enabled features.
+fn load_code(args: &Args, src: &str) -> Result<InputCode, ParserError> {
+    let features: HashSet<String> = args.features.iter().cloned().collect();
+    if args.file() == "-" {
+        let cwd = std::env::current_dir()
+            .map_err(|e| ParserError::SemanticError(format!("No working directory: {}", e)))?;
+        parser::parser::load_program_from_reader(src.as_bytes(), &cwd, &features)
+    } else {
+        parser::parser::load_program_with_features(Path::new(args.file()), &features)
+    }
+}
+
+// Parses the file and prints the warnings of the analysis, also when they are denied.
+fn check(args: &Args, src: &str) -> Result<Analysis, Diagnostic> {
+    let options = AnalyzeOptions {
+        deny_warnings: args.deny_warnings,
+        check_orphans: cfg!(debug_assertions),
+        ..Default::default()
+    };
+    let result = load_code(args, src)
+        .map_err(Diagnostic::from)
+        .and_then(|code| parser::parser::analyze_program_located(code, &HashMap::new(), &options));
+    let warnings = match &result {
+        Ok(analysis) => &analysis.warnings[..],
+        Err(d) => match &*d.error {
+            ParserError::DeniedWarnings(warnings) => &warnings[..],
+            _ => &[],
+        },
+    };
+    for warning in warnings {
+        println!("warning: {}", warning);
+    }
+    result
+}
+
+// Errors are printed with the statement they were raised for, and end the process.
+fn analyze(args: &Args) -> (Network, Vec<usize>) {
+    let src = read_source(args).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    });
+    let analysis = match check(args, &src) {
+        Ok(analysis) => analysis,
+        Err(d) => {
+            match &*d.error {
+                ParserError::DeniedWarnings(warnings) => eprintln!(
+                    "error: {} warnings denied by --deny-warnings",
+                    warnings.len()
+                ),
+                _ => eprintln!("{}", d.render(&src)),
+            }
+            std::process::exit(1);
+        }
+    };
+    (analysis.network, analysis.behavior_nodes)
+}
+
+// Prints the network in the --emit format. When it does, there is nothing to search.
+fn emit(args: &Args, network: &Network) -> bool {
+    match args.emit {
+        Some(Emit::Cir) => {
+            println!("{}", network.to_cir());
+            true
+        }
+        None => false,
+    }
 }
 
 fn _main(args: Args) {
@@ -31,12 +297,12 @@ fn _main(args: Args) {
         Device::Cpu
     };
 
-    let filename = &args.file;
-    let src = fs::read_to_string(filename).expect("Failed to read file");
-
+    let filename = args.file();
     println!("--- Parsing file: {:?} ---", filename);
-    let (network, behavior_nodes) =
-        parser::parser::parse(&src).expect(format!("Failed to parse {:?}", filename).as_str());
+    let (network, behavior_nodes) = analyze(&args);
+    if emit(&args, &network) {
+        return;
+    }
 
     let behavior_decl: &BehaviorDecl = match &network.nodes[behavior_nodes[0]].node_type {
         NodeType::Behavior(b) => b,
@@ -57,6 +323,7 @@ fn _main(args: Args) {
     let episodes_per_batch = 32;
     let seq_len = 32;
     let weights_path = behavior_decl.weights.clone();
+    let empty_reasons = behavior_decl.explain_empty();
     let (pool, model) = transformer::transformer_search(
         network,
         action_space,
@@ -68,14 +335,13 @@ fn _main(args: Args) {
         seq_len,
         &weights_path,
         500,
+        args.deadline(),
     );
 
-    println!("--- Expressions found ---");
-    for expr in pool.exprs() {
-        println!("{}", expr);
-    }
+    print_found(&pool, &args, &empty_reasons);
 
     let _ = pool.save_returns("returns.csv");
+    pool.save_provenance("provenance.json");
 
     save(&model, &weights_path);
     // save model weights
@@ -83,26 +349,25 @@ fn _main(args: Args) {
 
 fn _main_bruteforce(args: Args) {
     let use_cuda = args.cuda || std::env::var("CUDA_PATH").is_ok();
-    let filename = &args.file;
-    let src = fs::read_to_string(filename).expect("Failed to read file");
-
+    let filename = args.file();
     println!("--- Parsing file: {:?} ---", filename);
-    let (network, behavior_nodes) =
-        parser::parser::parse(&src).expect(format!("Failed to parse {:?}", filename).as_str());
+    let (network, behavior_nodes) = analyze(&args);
+    if emit(&args, &network) {
+        return;
+    }
 
     let behavior_decl: &BehaviorDecl = match &network.nodes[behavior_nodes[0]].node_type {
         NodeType::Behavior(b) => b,
         _ => unreachable!(),
     };
     let action_space: ActionSpace = behavior_decl.into();
-    let pool = bruteforce::brute_force(network, action_space, use_cuda);
+    let empty_reasons = behavior_decl.explain_empty();
+    let pool = bruteforce::brute_force(network, action_space, use_cuda, args.deadline());
 
-    println!("--- Expressions found ---");
-    for expr in pool.exprs() {
-        println!("{}", expr);
-    }
+    print_found(&pool, &args, &empty_reasons);
 
-    pool.save_returns("returns_brute.csv")
+    pool.save_returns("returns_brute.csv");
+    pool.save_provenance("provenance_brute.json")
 }
 
 fn _main_standard_ppo(args: Args) {
@@ -113,12 +378,12 @@ fn _main_standard_ppo(args: Args) {
         Device::Cpu
     };
 
-    let filename = &args.file;
-    let src = fs::read_to_string(filename).expect("Failed to read file");
-
+    let filename = args.file();
     println!("--- Parsing file: {:?} ---", filename);
-    let (network, behavior_nodes) =
-        parser::parser::parse(&src).expect(format!("Failed to parse {:?}", filename).as_str());
+    let (network, behavior_nodes) = analyze(&args);
+    if emit(&args, &network) {
+        return;
+    }
 
     let behavior_decl: &BehaviorDecl = match &network.nodes[behavior_nodes[0]].node_type {
         NodeType::Behavior(b) => b,
@@ -131,6 +396,7 @@ fn _main_standard_ppo(args: Args) {
     let episodes_per_batch = 8;
     let seq_len = 20;
     let weights_path = behavior_decl.weights.clone();
+    let empty_reasons = behavior_decl.explain_empty();
     let (pool, model) = transformer::transformer_search(
         network,
         action_space,
@@ -142,14 +408,13 @@ fn _main_standard_ppo(args: Args) {
         seq_len,
         &weights_path,
         100,
+        args.deadline(),
     );
 
-    println!("--- Expressions found ---");
-    for expr in pool.exprs() {
-        println!("{}", expr);
-    }
+    print_found(&pool, &args, &empty_reasons);
 
     let _ = pool.save_returns("returns_ppo.csv");
+    pool.save_provenance("provenance_ppo.json");
 
     save(&model, &weights_path);
 }
@@ -163,8 +428,20 @@ mod tests {
         let filename = "../examples/behavior_2.cm";
         let _src = fs::read_to_string(filename).expect("Failed to read file");
         _main_bruteforce(Args {
-            file: String::from(filename),
+            file: Some(String::from(filename)),
             cuda: true,
+            top: None,
+            rank: None,
+            sample: None,
+            seed: 0,
+            emit: None,
+            watch: false,
+            dump_symbols: false,
+            explain_empty: false,
+            deny_warnings: false,
+            timeout: None,
+            list_ops: false,
+            features: vec![],
         });
     }
     #[test]
@@ -172,8 +449,127 @@ mod tests {
         let filename = "../examples/behavior_2.cm";
         let _src = fs::read_to_string(filename).expect("Failed to read file");
         _main_standard_ppo(Args {
-            file: String::from(filename),
+            file: Some(String::from(filename)),
             cuda: true,
+            top: None,
+            rank: None,
+            sample: None,
+            seed: 0,
+            emit: None,
+            watch: false,
+            dump_symbols: false,
+            explain_empty: false,
+            deny_warnings: false,
+            timeout: None,
+            list_ops: false,
+            features: vec![],
         });
     }
+
+    #[test]
+    fn test_config_defaults() {
+        let config = || Config {
+            entry: Some("main.cm".to_string()),
+            top: Some(5),
+            ..Default::default()
+        };
+        let args = Args::parse_from(["comet"]).with_config(config());
+        assert_eq!(args.file(), "main.cm");
+        assert_eq!(args.top, Some(5));
+
+        let args =
+            Args::parse_from(["comet", "-f", "other.cm", "--top", "2"]).with_config(config());
+        assert_eq!(args.file(), "other.cm");
+        assert_eq!(args.top, Some(2));
+    }
+
+    #[test]
+    fn test_validate_args() {
+        let args = |argv: &[&str]| Args::parse_from(argv).with_config(Config::default());
+        assert_eq!(
+            args(&["comet"]).validate().unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument
+        );
+        assert!(args(&["comet", "--list-ops"]).validate().is_ok());
+        assert_eq!(
+            args(&["comet", "-f", "-", "--watch"])
+                .validate()
+                .unwrap_err()
+                .kind(),
+            ErrorKind::ArgumentConflict
+        );
+        assert!(
+            args(&["comet", "-f", "main.cm", "--watch"])
+                .validate()
+                .is_ok()
+        );
+        assert!(args(&["comet", "-f", "-"]).validate().is_ok());
+    }
+
+    #[test]
+    fn test_deadline() {
+        assert_eq!(Args::parse_from(["comet"]).deadline(), None);
+        let deadline = Args::parse_from(["comet", "--timeout", "0"])
+            .deadline()
+            .unwrap();
+        assert!(deadline <= Instant::now());
+    }
+
+    #[test]
+    fn test_sample_args() {
+        let args = Args::parse_from(["comet", "--sample", "5", "--seed", "42"]);
+        assert_eq!((args.sample, args.seed), (Some(5), 42));
+        assert!(Args::try_parse_from(["comet", "--sample", "5", "--top", "3"]).is_err());
+    }
+
+    #[test]
+    fn test_rank_args() {
+        let args = Args::parse_from(["comet", "--rank", "ir", "--top", "3"]);
+        assert_eq!((args.rank, args.top), (Some(Rank::Ir), Some(3)));
+        assert!(Args::try_parse_from(["comet", "--rank", "sharpe"]).is_err());
+    }
+
+    #[test]
+    fn test_emit_args() {
+        let args = Args::parse_from(["comet", "--emit", "cir"]);
+        assert_eq!(args.emit, Some(Emit::Cir));
+        assert!(Args::try_parse_from(["comet", "--emit", "json"]).is_err());
+        assert!(Args::try_parse_from(["comet", "--emit", "cir", "--watch"]).is_err());
+    }
+
+    #[test]
+    fn test_deny_warnings() {
+        let tmp = parser::testing::TempDir::new("deny_warnings");
+        let dir = tmp.path();
+        let file = dir.join("main.cm");
+        fs::write(
+            &file,
+            "Flow f {\n unused = data(\"open\")\n data(\"close\")\n}",
+        )
+        .unwrap();
+        let filename = file.to_str().unwrap();
+
+        let check_with = |flags: &[&str]| {
+            let args = Args::parse_from([&["comet", "-f", filename], flags].concat());
+            check(&args, &read_source(&args).unwrap())
+        };
+        let analysis = check_with(&[]).unwrap();
+        assert_eq!(analysis.warnings.len(), 1);
+        assert!(matches!(
+            check_with(&["--deny-warnings"]),
+            Err(d) if matches!(&*d.error, ParserError::DeniedWarnings(w) if *w == analysis.warnings)
+        ));
+    }
+
+    #[test]
+    fn test_check_error_span() {
+        let tmp = parser::testing::TempDir::new("check_error_span");
+        let dir = tmp.path();
+        let file = dir.join("main.cm");
+        fs::write(&file, "Flow f {\n x = data(\"close\")\n ts_sum(x, 0)\n}").unwrap();
+        let args = Args::parse_from(["comet", "-f", file.to_str().unwrap()]);
+        let src = read_source(&args).unwrap();
+        let rendered = check(&args, &src).unwrap_err().render(&src);
+        assert!(rendered.contains("3 |  ts_sum(x, 0)\n"), "{}", rendered);
+    }
 }
@@ -6,6 +6,7 @@ use rl::model::{AgentModel, Model};
 use rl::pool::Pool;
 use runtime::backtest::BasicBacktest;
 use runtime::runtime::Runtime;
+use std::time::Instant;
 use tch::Device;
 use tch::Tensor;
 use tch::nn::{Module, OptimizerConfig};
@@ -67,6 +68,7 @@ pub fn transformer_search(
     seq_len: usize,
     weights_path: &Option<String>,
     num_iterations: usize,
+    deadline: Option<Instant>, // checked between iterations, the pool so far is returned
 ) -> (rl::pool::Pool, tch::nn::VarStore) {
     // let mut runtime = Runtime::new(10000, "data".into(), Some(device));
     let backtester = BasicBacktest::new(&mut runtime.dmgr, "returns_d1");
@@ -101,6 +103,10 @@ pub fn transformer_search(
     let unfinished_penalty: f64 = 10.0;
 
     for iteration in 0..num_iterations {
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            println!("--- Time budget reached after {} iterations ---", iteration);
+            break;
+        }
         println!("--- Iteration {} ---", iteration);
         buffer.clear();
 
@@ -229,7 +235,11 @@ pub fn transformer_search(
                 if is_done {
                     // insert to pool
                     let callgraph = env.state.machine.callgraph.clone();
-                    env.pool.insert(&mut runtime, callgraph);
+                    let path: Vec<Action> = actions
+                        .iter()
+                        .map(|&idx| env.action_space.get_action(idx as usize))
+                        .collect();
+                    env.pool.insert(&mut runtime, callgraph, &path);
                     break;
                 }
             }
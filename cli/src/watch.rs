@@ -0,0 +1,111 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const DEBOUNCE: Duration = Duration::from_millis(200); // editors often write a file in several steps
+
+// Polls the modification times of a set of files.
+pub struct Watcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl Watcher {
+    pub fn new(files: Vec<PathBuf>) -> Self {
+        let mut watcher = Watcher { files: Vec::new() };
+        watcher.set_files(files);
+        watcher
+    }
+
+    // Replaces the watched files, e.g. after the imports changed.
+    pub fn set_files(&mut self, files: Vec<PathBuf>) {
+        self.files = files
+            .into_iter()
+            .map(|f| {
+                let t = modified(&f);
+                (f, t)
+            })
+            .collect();
+    }
+
+    // True if any file was modified, created or removed since the last poll.
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        for (path, stamp) in self.files.iter_mut() {
+            let now = modified(path);
+            if now != *stamp {
+                *stamp = now;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+// Calls on_change whenever the file or one of the imports of the enabled features is saved.
+// Never returns.
+pub fn watch(path: &Path, features: &HashSet<String>, mut on_change: impl FnMut()) {
+    let files = |path: &Path| {
+        parser::parser::source_files(path, features).unwrap_or_else(|_| vec![path.to_path_buf()])
+    };
+    let mut watcher = Watcher::new(files(path));
+    on_change();
+    loop {
+        sleep(POLL_INTERVAL);
+        if watcher.poll() {
+            sleep(DEBOUNCE);
+            while watcher.poll() {
+                sleep(DEBOUNCE);
+            }
+            on_change();
+            watcher.set_files(files(path));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn test_watcher_poll() {
+        let tmp = parser::testing::TempDir::new("watch");
+        let dir = tmp.path();
+        let lib = dir.join("lib.cm");
+        let main = dir.join("main.cm");
+        let debug = dir.join("debug.cm");
+        std::fs::write(&lib, "Behavior B() -> DataFrame").unwrap();
+        std::fs::write(&debug, "Behavior D() -> DataFrame").unwrap();
+        std::fs::write(
+            &main,
+            "Import \"lib.cm\"\nImport \"debug.cm\" If debug\nFlow f { data(\"close\") }",
+        )
+        .unwrap();
+
+        // A guarded import is watched only when its feature is enabled.
+        let debug_on: HashSet<String> = ["debug".to_string()].into();
+        let files = parser::parser::source_files(&main, &debug_on).unwrap();
+        assert_eq!(files.len(), 3);
+        let files = parser::parser::source_files(&main, &HashSet::new()).unwrap();
+        assert_eq!(files.len(), 2);
+        let mut watcher = Watcher::new(files);
+        assert!(!watcher.poll());
+
+        // Editing an import is a change too.
+        let later = SystemTime::now() + Duration::from_secs(10);
+        File::options()
+            .write(true)
+            .open(&lib)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+    }
+}
@@ -1,8 +1,54 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 
 use crate::{behavior::BehaviorDecl, expr::Literal};
 use stdlib::OperatorSpec;
 
+// Maps node indices of a network to the indices after a transformation pass.
+pub type NodeRemap = HashMap<usize, usize>;
+
+// Follows a node through two consecutive passes. Nodes dropped by either pass are dropped.
+pub fn compose_remaps(first: &NodeRemap, second: &NodeRemap) -> NodeRemap {
+    first
+        .iter()
+        .filter_map(|(old, mid)| second.get(mid).map(|new| (*old, *new)))
+        .collect()
+}
+
+// Prefix tree over the pre-order tokens of several networks. Variants that share an early
+// computation share a path and branch where they first differ. Each leaf is one variant.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct VariantTree {
+    pub branches: Vec<(String, VariantTree)>, // next token and its subtree, in order of appearance
+}
+
+impl VariantTree {
+    pub fn from_networks<'a>(networks: impl IntoIterator<Item = &'a Network>) -> Self {
+        let mut tree = VariantTree::default();
+        for network in networks {
+            let mut node = &mut tree;
+            for token in network.prefix_tokens() {
+                let pos = match node.branches.iter().position(|(t, _)| *t == token) {
+                    Some(pos) => pos,
+                    None => {
+                        node.branches.push((token, VariantTree::default()));
+                        node.branches.len() - 1
+                    }
+                };
+                node = &mut node.branches[pos].1;
+            }
+        }
+        tree
+    }
+
+    pub fn leaves(&self) -> usize {
+        if self.branches.is_empty() {
+            return 1;
+        }
+        self.branches.iter().map(|(_, t)| t.leaves()).sum()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct Network {
     pub nodes: Vec<Node>,
@@ -22,6 +68,14 @@ pub enum NodeType {
     Behavior(BehaviorDecl),
 }
 
+// Leaf inputs of a network. A source is loaded by the runtime with data("name"),
+// while a parameter is a literal argument, fixed when the network is built.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    Source { name: String, node: usize },
+    Parameter { node: usize },
+}
+
 impl Network {
     pub fn new() -> Self {
         Self {
@@ -64,6 +118,133 @@ impl Network {
         new_network
     }
 
+    // Concatenates independent networks into one, offsetting the node indices of each, and
+    // returns the root of every input in the merged network. A combining node added on top of
+    // those roots, e.g. add(a, b) for an ensemble, becomes the root; until then it is the first.
+    pub fn merge(networks: &[Network]) -> (Network, Vec<usize>) {
+        let mut merged = Network::new();
+        let mut roots = Vec::with_capacity(networks.len());
+        for network in networks {
+            let offset = merged.nodes.len();
+            merged.nodes.extend(network.nodes.iter().map(|node| Node {
+                node_type: node.node_type.clone(),
+                children: node.children.iter().map(|c| c + offset).collect(),
+            }));
+            roots.push(network.root + offset);
+        }
+        merged.root = roots.first().copied().unwrap_or(0);
+        (merged, roots)
+    }
+
+    // Nodes from index `since` on that are not reachable from `node_id`, in index order.
+    pub fn unreachable_from(&self, node_id: usize, since: usize) -> Vec<usize> {
+        let mut reachable = vec![false; self.nodes.len()];
+        let mut stack = vec![node_id];
+        while let Some(id) = stack.pop() {
+            if !reachable[id] {
+                reachable[id] = true;
+                stack.extend(&self.nodes[id].children);
+            }
+        }
+        (since..self.nodes.len())
+            .filter(|&i| !reachable[i])
+            .collect()
+    }
+
+    // Drops nodes unreachable from the root, keeping the order of the remaining nodes.
+    pub fn prune(&self) -> (Network, NodeRemap) {
+        if self.nodes.is_empty() {
+            return (self.clone(), NodeRemap::new());
+        }
+        let mut reachable = vec![false; self.nodes.len()];
+        let mut stack = vec![self.root];
+        while let Some(id) = stack.pop() {
+            if !reachable[id] {
+                reachable[id] = true;
+                stack.extend(&self.nodes[id].children);
+            }
+        }
+        let kept: Vec<usize> = (0..self.nodes.len()).filter(|&i| reachable[i]).collect();
+        let remap: NodeRemap = kept
+            .iter()
+            .enumerate()
+            .map(|(new_id, &old_id)| (old_id, new_id))
+            .collect();
+        (self.rebuild(&kept, &remap), remap)
+    }
+
+    // Merges structurally identical subtrees (common subexpression elimination).
+    // Behavior nodes are never merged, each one is searched on its own.
+    pub fn dedup(&self) -> (Network, NodeRemap) {
+        if self.nodes.is_empty() {
+            return (self.clone(), NodeRemap::new());
+        }
+        // A node is keyed on its label and the representatives of its children.
+        fn find(
+            network: &Network,
+            id: usize,
+            representative: &mut [Option<usize>],
+            first_seen: &mut HashMap<(String, Vec<usize>), usize>,
+        ) -> usize {
+            if let Some(r) = representative[id] {
+                return r;
+            }
+            let node = &network.nodes[id];
+            let children: Vec<usize> = node
+                .children
+                .iter()
+                .map(|&c| find(network, c, representative, first_seen))
+                .collect();
+            let r = match &node.node_type {
+                NodeType::Behavior(_) => id,
+                NodeType::Operator(op) => *first_seen
+                    .entry((op.name.to_string(), children))
+                    .or_insert(id),
+                NodeType::Literal(lit) => *first_seen
+                    .entry((format!("{:?}", lit), children))
+                    .or_insert(id),
+            };
+            representative[id] = Some(r);
+            r
+        }
+        let mut representative = vec![None; self.nodes.len()];
+        let mut first_seen = HashMap::new();
+        for id in 0..self.nodes.len() {
+            find(self, id, &mut representative, &mut first_seen);
+        }
+        let representative: Vec<usize> = representative.into_iter().flatten().collect();
+        let kept: Vec<usize> = (0..self.nodes.len())
+            .filter(|&i| representative[i] == i)
+            .collect();
+        let compact: HashMap<usize, usize> = kept
+            .iter()
+            .enumerate()
+            .map(|(new_id, &old_id)| (old_id, new_id))
+            .collect();
+        let remap: NodeRemap = (0..self.nodes.len())
+            .map(|i| (i, compact[&representative[i]]))
+            .collect();
+        (self.rebuild(&kept, &remap), remap)
+    }
+
+    fn rebuild(&self, kept: &[usize], remap: &NodeRemap) -> Network {
+        let nodes = kept
+            .iter()
+            .map(|&old_id| Node {
+                node_type: self.nodes[old_id].node_type.clone(),
+                children: self.nodes[old_id]
+                    .children
+                    .iter()
+                    .map(|c| remap[c])
+                    .collect(),
+            })
+            .collect();
+        Network {
+            nodes,
+            root: remap[&self.root],
+        }
+    }
+
     pub fn format_node(&self, node_id: usize) -> String {
         // Used to hash for caching
         if node_id >= self.nodes.len() {
@@ -91,6 +272,123 @@ impl Network {
         }
     }
 
+    // format_node with the operands of commutative operators sorted, so that expressions that
+    // only differ in operand order, e.g. add(a, b) and add(b, a), have the same form.
+    pub fn canonical_form(&self, node_id: usize) -> String {
+        if node_id >= self.nodes.len() {
+            return String::new();
+        }
+        let node = &self.nodes[node_id];
+        match &node.node_type {
+            NodeType::Operator(op) => {
+                let mut params: Vec<String> = node
+                    .children
+                    .iter()
+                    .map(|&c| self.canonical_form(c))
+                    .collect();
+                if COMMUTATIVE_OPERATORS.contains(&op.name) {
+                    params.sort();
+                }
+                format!("{}({})", op.name, params.join(", "))
+            }
+            NodeType::Behavior(behavior) => {
+                let params: Vec<String> = node
+                    .children
+                    .iter()
+                    .map(|&c| self.canonical_form(c))
+                    .collect();
+                format!(
+                    "{}({})",
+                    behavior.name.as_ref().unwrap_or(&"_".into()),
+                    params.join(", ")
+                )
+            }
+            NodeType::Literal(lit) => format!("{}", lit),
+        }
+    }
+
+    // Number of nodes in the expression tree under node_id, counting shared subtrees once per use.
+    pub fn complexity(&self, node_id: usize) -> usize {
+        if node_id >= self.nodes.len() {
+            return 0;
+        }
+        1 + self.nodes[node_id]
+            .children
+            .iter()
+            .map(|&c| self.complexity(c))
+            .sum::<usize>()
+    }
+
+    // Compact S-expression rooted at the output node, e.g. (divide (data "volume") 2.0).
+    // Easier to diff and assert on than the call-style format_node.
+    pub fn to_sexpr(&self) -> String {
+        if self.nodes.is_empty() {
+            return "()".to_string();
+        }
+        self.sexpr_node(self.root)
+    }
+
+    fn sexpr_node(&self, node_id: usize) -> String {
+        let node = &self.nodes[node_id];
+        let head = match &node.node_type {
+            NodeType::Literal(lit) => return format!("{}", lit),
+            NodeType::Operator(op) => op.name.to_string(),
+            NodeType::Behavior(b) => b.name.clone().unwrap_or("_".into()),
+        };
+        let mut parts = vec![head];
+        parts.extend(node.children.iter().map(|&c| self.sexpr_node(c)));
+        format!("({})", parts.join(" "))
+    }
+
+    // Node labels of the expression under the root in pre-order (Polish notation).
+    // Arities are fixed per operator, so the tokens identify the expression.
+    pub fn prefix_tokens(&self) -> Vec<String> {
+        fn visit(network: &Network, node_id: usize, out: &mut Vec<String>) {
+            let node = &network.nodes[node_id];
+            out.push(match &node.node_type {
+                NodeType::Literal(lit) => format!("{}", lit),
+                NodeType::Operator(op) => op.name.to_string(),
+                NodeType::Behavior(b) => b.name.clone().unwrap_or("_".into()),
+            });
+            for &c in &node.children {
+                visit(network, c, out);
+            }
+        }
+        let mut tokens = Vec::new();
+        if !self.nodes.is_empty() {
+            visit(self, self.root, &mut tokens);
+        }
+        tokens
+    }
+
+    // Every leaf once, in node order. The name literal of a source is part of the source.
+    pub fn inputs(&self) -> Vec<Input> {
+        let source_name = |node: &Node| match (&node.node_type, &node.children[..]) {
+            (NodeType::Operator(op), &[c]) if op.name == "data" => match &self.nodes[c].node_type {
+                NodeType::Literal(Literal::String(name)) => Some((c, name)),
+                _ => None,
+            },
+            _ => None,
+        };
+        let names: HashSet<usize> = self
+            .nodes
+            .iter()
+            .filter_map(|n| source_name(n).map(|(c, _)| c))
+            .collect();
+        let mut inputs = Vec::new();
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if let Some((_, name)) = source_name(node) {
+                inputs.push(Input::Source {
+                    name: name.clone(),
+                    node: idx,
+                });
+            } else if matches!(node.node_type, NodeType::Literal(_)) && !names.contains(&idx) {
+                inputs.push(Input::Parameter { node: idx });
+            }
+        }
+        inputs
+    }
+
     pub fn get_behavior_indices(&self) -> Vec<usize> {
         self.nodes
             .iter()
@@ -129,6 +427,8 @@ impl fmt::Display for Network {
     }
 }
 
+const COMMUTATIVE_OPERATORS: [&str; 4] = ["add", "multiply", "rank_add", "rank_mul"];
+
 #[cfg(test)]
 mod tests {
     use stdlib::types::Signal;
@@ -174,6 +474,30 @@ mod tests {
             )
         );
 
+        assert_eq!(
+            network.inputs(),
+            vec![
+                Input::Source {
+                    name: "volume".to_string(),
+                    node: op1
+                },
+                Input::Source {
+                    name: "adv20".to_string(),
+                    node: op2
+                },
+                Input::Parameter { node: lit3 },
+            ]
+        );
+
+        assert_eq!(network.complexity(root), 7);
+        assert_eq!(
+            network.to_sexpr(),
+            format!(
+                "(divide (Mix (data \"volume\") (data \"adv20\")) {})",
+                Literal::Float(2.0)
+            )
+        );
+
         // 2. Replace Mix with add operator.
         let behavior_idx = mixed;
         let behavior_node = &mut network.nodes[behavior_idx];
@@ -190,4 +514,169 @@ mod tests {
         );
         println!("{}", display_str);
     }
+
+    #[test]
+    fn test_remap_prune_then_dedup() {
+        // divide(data("volume"), data("volume")) with a dangling literal at index 0
+        let mut network = Network::new();
+        let dangling = network.add_node(NodeType::Literal(Literal::Integer(5)));
+        let lit1 = network.add_node(NodeType::Literal(Literal::String("volume".to_string())));
+        let op1 = network.add_node(NodeType::Operator("data".into()));
+        network.add_child(op1, lit1);
+        let lit2 = network.add_node(NodeType::Literal(Literal::String("volume".to_string())));
+        let op2 = network.add_node(NodeType::Operator("data".into()));
+        network.add_child(op2, lit2);
+        let root = network.add_node(NodeType::Operator("divide".into()));
+        network.add_child(root, op1);
+        network.add_child(root, op2);
+        network.root = root;
+
+        let (pruned, prune_remap) = network.prune();
+        assert_eq!(pruned.nodes.len(), 5);
+        assert!(!prune_remap.contains_key(&dangling));
+
+        let (deduped, dedup_remap) = pruned.dedup();
+        assert_eq!(deduped.nodes.len(), 3);
+        assert_eq!(deduped.format_node(deduped.root), network.format_node(root));
+
+        let remap = compose_remaps(&prune_remap, &dedup_remap);
+        assert_eq!(remap[&root], deduped.root);
+        assert_eq!(remap[&op1], remap[&op2]);
+        assert_eq!(deduped.format_node(remap[&op2]), "data(\"volume\")");
+        assert!(!remap.contains_key(&dangling));
+    }
+
+    #[test]
+    fn test_dedup_keeps_behaviors() {
+        let (network, behaviors) = crate::parser::parse(
+            r#"
+            Behavior B(x: DataFrame) { operators = [rank] } -> DataFrame
+            Flow f { add(B(data("close")), B(data("close"))) }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(behaviors.len(), 2);
+        let (deduped, remap) = network.dedup();
+        assert_ne!(remap[&behaviors[0]], remap[&behaviors[1]]);
+        // The shared data("close") operand is merged below them.
+        let close = |b: usize| deduped.nodes[remap[&b]].children[0];
+        assert_eq!(close(behaviors[0]), close(behaviors[1]));
+        assert_eq!(deduped.nodes.len(), network.nodes.len() - 2);
+    }
+
+    #[test]
+    fn test_variant_tree() {
+        // divide(ts_mean(data("volume"), 20), X) with X chosen late as rank or flip of data("close")
+        let variant = |last: &str| {
+            let mut network = Network::new();
+            let lit1 = network.add_node(NodeType::Literal(Literal::String("volume".to_string())));
+            let volume = network.add_node(NodeType::Operator("data".into()));
+            network.add_child(volume, lit1);
+            let window = network.add_node(NodeType::Literal(Literal::Integer(20)));
+            let mean = network.add_node(NodeType::Operator("ts_mean".into()));
+            network.add_child(mean, volume);
+            network.add_child(mean, window);
+            let lit2 = network.add_node(NodeType::Literal(Literal::String("close".to_string())));
+            let close = network.add_node(NodeType::Operator("data".into()));
+            network.add_child(close, lit2);
+            let choice = network.add_node(NodeType::Operator(last.into()));
+            network.add_child(choice, close);
+            let root = network.add_node(NodeType::Operator("divide".into()));
+            network.add_child(root, mean);
+            network.add_child(root, choice);
+            network.root = root;
+            network
+        };
+        let variants = vec![variant("rank"), variant("flip"), variant("rank")];
+        assert_eq!(
+            variants[0].prefix_tokens(),
+            vec![
+                "divide",
+                "ts_mean",
+                "data",
+                "\"volume\"",
+                "20",
+                "rank",
+                "data",
+                "\"close\""
+            ]
+        );
+
+        let tree = VariantTree::from_networks(&variants);
+        assert_eq!(tree.branches.len(), 1);
+        assert_eq!(tree.branches[0].0, "divide");
+        assert_eq!(tree.leaves(), 2);
+
+        // The shared computation is stored once, above the branch point.
+        let mut node = &tree;
+        for _ in 0..5 {
+            assert_eq!(node.branches.len(), 1);
+            node = &node.branches[0].1;
+        }
+        let choices: Vec<&str> = node.branches.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(choices, vec!["rank", "flip"]);
+    }
+
+    #[test]
+    fn test_canonical_form() {
+        let form = |src: &str| {
+            let (network, _) = crate::parser::parse(src).unwrap();
+            network.canonical_form(network.root)
+        };
+        assert_eq!(
+            form(r#"Flow f { add(data("b"), rank(data("a"))) }"#),
+            form(r#"Flow f { add(rank(data("a")), data("b")) }"#)
+        );
+        assert_eq!(
+            form(r#"Flow f { flip(multiply(data("b"), data("a"))) }"#),
+            "flip(multiply(data(\"a\"), data(\"b\")))"
+        );
+        assert_ne!(
+            form(r#"Flow f { subtract(data("b"), data("a")) }"#),
+            form(r#"Flow f { subtract(data("a"), data("b")) }"#)
+        );
+        // Operands are sorted at every level, after their own operands are.
+        assert_eq!(
+            form(r#"Flow f { add(multiply(data("b"), data("a")), data("c")) }"#),
+            form(r#"Flow f { add(data("c"), multiply(data("a"), data("b"))) }"#)
+        );
+        assert_eq!(
+            form(r#"Flow f { rank_add(data("z"), ts_mean(add(data("y"), data("x")), 5)) }"#),
+            "rank_add(data(\"z\"), ts_mean(add(data(\"x\"), data(\"y\")), 5))"
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        // rank(data("close")) and ts_mean(data("volume"), 20) as independent sub-flows.
+        let mut a = Network::new();
+        let lit = a.add_node(NodeType::Literal(Literal::String("close".to_string())));
+        let close = a.add_node(NodeType::Operator("data".into()));
+        a.add_child(close, lit);
+        a.root = a.add_node(NodeType::Operator("rank".into()));
+        a.add_child(a.root, close);
+
+        let mut b = Network::new();
+        let lit = b.add_node(NodeType::Literal(Literal::String("volume".to_string())));
+        let volume = b.add_node(NodeType::Operator("data".into()));
+        b.add_child(volume, lit);
+        let window = b.add_node(NodeType::Literal(Literal::Integer(20)));
+        b.root = b.add_node(NodeType::Operator("ts_mean".into()));
+        b.add_child(b.root, volume);
+        b.add_child(b.root, window);
+
+        let (mut merged, roots) = Network::merge(&[a.clone(), b.clone()]);
+        assert_eq!(merged.nodes.len(), a.nodes.len() + b.nodes.len());
+        assert_eq!(roots, vec![2, 6]);
+        assert_eq!(merged.format_node(roots[0]), a.format_node(a.root));
+        assert_eq!(merged.format_node(roots[1]), b.format_node(b.root));
+
+        // Combined on top, both sub-flows are reachable and nothing is left over.
+        let sum = merged.add_node(NodeType::Operator("add".into()));
+        merged.add_child(sum, roots[0]);
+        merged.add_child(sum, roots[1]);
+        merged.root = sum;
+        assert_eq!(merged.unreachable_from(sum, 0), Vec::<usize>::new());
+        assert_eq!(merged.prune().0.nodes.len(), merged.nodes.len());
+    }
 }
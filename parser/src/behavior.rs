@@ -1,21 +1,26 @@
 // input code AST
 use crate::expr::FlowStmt;
-use crate::expr::Ident;
+use crate::expr::{Expr, Ident};
+use serde::{Deserialize, Serialize};
+use stdlib::OperatorSpec;
 use stdlib::types::Signal;
 pub type InputCode = Vec<InputDecl>;
 pub type NamedSignal = (String, Signal);
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum InputDecl {
-    Import(String),
+    Import(ImportDecl),
     Behavior(BehaviorDecl),
     Flow(FlowDecl),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BehaviorDecl {
     pub name: Option<String>,
+    #[serde(with = "crate::cir::signal_list")]
     pub inputs: Vec<Signal>,
+    pub defaults: Vec<Option<Expr>>, // per input, used when a call omits trailing arguments
+    #[serde(with = "crate::cir::signal")]
     pub output: Signal,
 
     pub operators: Option<Vec<Ident>>,
@@ -26,12 +31,17 @@ pub struct BehaviorDecl {
     pub weights: Option<String>,
     pub train: Option<bool>,
     pub supervised_epochs: Option<usize>,
+    pub priority: Option<i64>, // behaviors with higher priority are searched first
+    pub category: Option<String>,
+    pub deprecated: Option<String>, // reason given by @deprecated("...")
+    pub doc: Option<String>,        // /// lines before the declaration, one per line
 }
 
 impl BehaviorDecl {
     pub fn new(name: &str, inputs: Vec<Signal>, output: Signal) -> Self {
         Self {
             name: Some(name.to_string()),
+            defaults: vec![None; inputs.len()],
             inputs: inputs,
             output: output, //Signal::DataFrame(None),
             operators: None,
@@ -41,31 +51,139 @@ impl BehaviorDecl {
             weights: None,
             train: None,
             supervised_epochs: None,
+            priority: None,
+            category: None,
+            deprecated: None,
+            doc: None,
         }
     }
-}
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct FlowDecl {
-    pub name: Ident,
-    pub body: Vec<FlowStmt>,
-}
+    // Removes repeated entries from the search space, keeping the first occurrence.
+    // Duplicates would otherwise map two action indices onto the same action.
+    pub fn dedup_lists(&mut self) {
+        fn dedup<T: Clone, K: PartialEq>(items: &mut Option<Vec<T>>, key: impl Fn(&T) -> K) {
+            if let Some(v) = items {
+                let mut seen: Vec<K> = Vec::new();
+                v.retain(|x| {
+                    let k = key(x);
+                    if seen.contains(&k) {
+                        false
+                    } else {
+                        seen.push(k);
+                        true
+                    }
+                });
+            }
+        }
+        dedup(&mut self.operators, |x| x.clone());
+        dedup(&mut self.integers, |x| *x);
+        dedup(&mut self.floats, |x| x.to_bits());
+        dedup(&mut self.strings, |x| x.clone());
+    }
 
-use std::fmt;
+    // Every argument may be omitted, so the bare name stands for a call, e.g. y = Generator
+    pub fn is_nullary(&self) -> bool {
+        self.defaults.iter().all(Option::is_some)
+    }
 
-impl fmt::Display for InputDecl {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            InputDecl::Import(i) => write!(f, "{}", i),
-            InputDecl::Behavior(b) => write!(f, "{:?}", b),
-            InputDecl::Flow(flow) => write!(f, "{:?}", flow),
+    // Name, argument types with defaults, and output type, e.g. Smoother(DataFrame, Int = 21) -> DataFrame
+    pub fn signature(&self) -> String {
+        let args: Vec<String> = self
+            .inputs
+            .iter()
+            .zip(self.defaults.iter())
+            .map(|(sig, default)| match default {
+                Some(d) => format!("{} = {}", signal_type_name(sig), d),
+                None => signal_type_name(sig).to_string(),
+            })
+            .collect();
+        format!(
+            "{}({}) -> {}",
+            self.name.as_deref().unwrap_or("_"),
+            args.join(", "),
+            signal_type_name(&self.output)
+        )
+    }
+
+    // Why a search over this behavior can find nothing: operators whose inputs no parameter,
+    // constant list or other usable operator can supply, and an output type nothing produces.
+    // Empty when every operator is usable.
+    pub fn explain_empty(&self) -> Vec<String> {
+        let same = |a: &Signal, b: &Signal| std::mem::discriminant(a) == std::mem::discriminant(b);
+        let mut available: Vec<Signal> = self.inputs.clone();
+        let constants = [
+            (
+                self.integers.as_ref().is_some_and(|v| !v.is_empty()),
+                Signal::Int(None),
+            ),
+            (
+                self.floats.as_ref().is_some_and(|v| !v.is_empty()),
+                Signal::Float(None),
+            ),
+            (
+                self.strings.as_ref().is_some_and(|v| !v.is_empty()),
+                Signal::String(None),
+            ),
+        ];
+        for (present, sig) in constants {
+            if present {
+                available.push(sig);
+            }
+        }
+        let specs: Vec<&stdlib::OperatorSpec> = self
+            .operators
+            .iter()
+            .flatten()
+            .filter_map(|op| stdlib::OperatorSpec::lookup(op))
+            .collect();
+        // Operator outputs feed other operators, so grow the available types to a fixed point.
+        loop {
+            let before = available.len();
+            for spec in &specs {
+                let usable = spec
+                    .inputs
+                    .iter()
+                    .all(|i| available.iter().any(|a| same(a, i)));
+                if usable && !available.iter().any(|a| same(a, &spec.output_shape)) {
+                    available.push(spec.output_shape.clone());
+                }
+            }
+            if available.len() == before {
+                break;
+            }
         }
+
+        let name = self.name.as_deref().unwrap_or("_");
+        let mut reasons = Vec::new();
+        for spec in &specs {
+            if let Some(missing) = spec
+                .inputs
+                .iter()
+                .find(|i| !available.iter().any(|a| same(a, i)))
+            {
+                let needs: Vec<&str> = spec.inputs.iter().map(signal_type_name).collect();
+                reasons.push(format!(
+                    "{}: {}({}) needs an argument of type {}, which {} cannot supply",
+                    name,
+                    spec.name,
+                    needs.join(", "),
+                    signal_type_name(missing),
+                    name
+                ));
+            }
+        }
+        if !available.iter().any(|a| same(a, &self.output)) {
+            reasons.push(format!(
+                "{}: nothing produces the output type {}",
+                name,
+                signal_type_name(&self.output)
+            ));
+        }
+        reasons
     }
-}
 
-impl fmt::Display for BehaviorDecl {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let args: Vec<String> = self.inputs.iter().map(|sig| format!("{:?}", sig)).collect();
+    // Properties in declaration syntax, e.g. operators = [add, divide]
+    pub fn properties(&self) -> Vec<String> {
         let mut props = Vec::new();
         if let Some(w) = &self.weights {
             props.push(format!("weights = \"{}\"", w));
@@ -76,6 +194,12 @@ impl fmt::Display for BehaviorDecl {
         if let Some(ss) = self.supervised_epochs {
             props.push(format!("supervised_epochs = {}", ss));
         }
+        if let Some(p) = self.priority {
+            props.push(format!("priority = {}", p));
+        }
+        if let Some(c) = &self.category {
+            props.push(format!("category = \"{}\"", c));
+        }
         if let Some(ops) = &self.operators {
             props.push(format!("operators = [{}]", ops.join(", ")));
         }
@@ -91,7 +215,232 @@ impl fmt::Display for BehaviorDecl {
             let s: Vec<String> = strs.iter().map(|s| format!("\"{}\"", s)).collect();
             props.push(format!("strings = [{}]", s.join(", ")));
         }
+        props
+    }
+}
 
+// Signal for a type name of the language. "Vector" is an alias of DataFrame.
+pub fn signal_type(name: &str) -> Option<Signal> {
+    match name {
+        "Void" => Some(Signal::Void),
+        "Float" => Some(Signal::Float(None)),
+        "Int" => Some(Signal::Int(None)),
+        "String" => Some(Signal::String(None)),
+        "Vector" | "DataFrame" => Some(Signal::DataFrame(None)),
+        _ => None,
+    }
+}
+
+pub fn signal_type_name(sig: &Signal) -> &'static str {
+    match sig {
+        Signal::Void => "Void",
+        Signal::Float(_) => "Float",
+        Signal::Int(_) => "Int",
+        Signal::String(_) => "String",
+        Signal::DataFrame(_) => "DataFrame",
+    }
+}
+
+// Every operator with its argument and output types, e.g. ts_mean(DataFrame, Int) -> DataFrame
+pub fn operator_table() -> String {
+    OperatorSpec::all()
+        .iter()
+        .map(|spec| {
+            let args: Vec<&str> = spec.inputs.iter().map(signal_type_name).collect();
+            format!(
+                "{}({}) -> {}",
+                spec.name,
+                args.join(", "),
+                signal_type_name(&spec.output_shape)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Behaviors whose first argument accepts a value of the given type, in declaration order.
+pub fn applicable_behaviors(code: &InputCode, sig: &Signal) -> Vec<String> {
+    code.iter()
+        .filter_map(|decl| match decl {
+            InputDecl::Behavior(b) => Some(b),
+            _ => None,
+        })
+        .filter(|b| {
+            b.inputs
+                .first()
+                .is_some_and(|first| std::mem::discriminant(first) == std::mem::discriminant(sig))
+        })
+        .map(|b| b.name.clone().unwrap_or("_".to_string()))
+        .collect()
+}
+
+// Why a call to `name` with arguments of the given types would be rejected, one reason per
+// candidate (the behavior declared in the code, then the stdlib operator). Empty when one accepts it.
+pub fn why_no_match(code: &InputCode, name: &str, provided: &[Signal]) -> Vec<String> {
+    fn mismatches(
+        label: &str,
+        expected: &[Signal],
+        required: usize,
+        provided: &[Signal],
+    ) -> Option<String> {
+        if provided.len() < required || provided.len() > expected.len() {
+            let arity = if required == expected.len() {
+                expected.len().to_string()
+            } else {
+                format!("{} to {}", required, expected.len())
+            };
+            return Some(format!(
+                "{}: expects {} arguments, got {}",
+                label,
+                arity,
+                provided.len()
+            ));
+        }
+        let (index, (got, want)) =
+            provided
+                .iter()
+                .zip(expected)
+                .enumerate()
+                .find(|(_, (got, want))| {
+                    std::mem::discriminant(*got) != std::mem::discriminant(*want)
+                })?;
+        Some(format!(
+            "{}: argument {} is {}, expected {}",
+            label,
+            index + 1,
+            signal_type_name(got),
+            signal_type_name(want)
+        ))
+    }
+
+    let mut reasons = Vec::new();
+    let mut candidates = 0;
+    for decl in code {
+        if let InputDecl::Behavior(b) = decl
+            && b.name.as_deref() == Some(name)
+        {
+            candidates += 1;
+            let required = b.defaults.iter().take_while(|d| d.is_none()).count();
+            match mismatches(&b.signature(), &b.inputs, required, provided) {
+                Some(reason) => reasons.push(reason),
+                None => return Vec::new(),
+            }
+        }
+    }
+    if let Some(spec) = stdlib::OperatorSpec::lookup(name) {
+        candidates += 1;
+        let inputs: Vec<&str> = spec.inputs.iter().map(signal_type_name).collect();
+        let label = format!(
+            "{}({}) -> {}",
+            spec.name,
+            inputs.join(", "),
+            signal_type_name(&spec.output_shape)
+        );
+        match mismatches(&label, spec.inputs, spec.inputs.len(), provided) {
+            Some(reason) => reasons.push(reason),
+            None => return Vec::new(),
+        }
+    }
+    if candidates == 0 {
+        reasons.push(format!("No behavior or operator named {}", name));
+    }
+    reasons
+}
+
+// Behaviors with their signatures and properties, then flows, in declaration order.
+pub fn symbol_table(code: &InputCode) -> String {
+    let mut lines = vec!["Behaviors:".to_string()];
+    for decl in code {
+        if let InputDecl::Behavior(b) = decl {
+            lines.push(format!("  {}", b.signature()));
+            for line in b.doc.iter().flat_map(|doc| doc.lines()) {
+                lines.push(format!("    /// {}", line));
+            }
+            if let Some(reason) = &b.deprecated {
+                lines.push(format!("    @deprecated(\"{}\")", reason));
+            }
+            for prop in b.properties() {
+                lines.push(format!("    {}", prop));
+            }
+        }
+    }
+    lines.push("Flows:".to_string());
+    for decl in code {
+        if let InputDecl::Flow(f) = decl {
+            let mut line = format!("  {}", f.name);
+            if f.export {
+                line.push_str(" (exported)");
+            }
+            if let Some(b) = &f.implements {
+                line.push_str(&format!(" implements {}", b));
+            }
+            lines.push(line);
+        }
+    }
+    lines.join("\n")
+}
+
+pub fn behaviors_in_category<'a>(code: &'a InputCode, category: &str) -> Vec<&'a BehaviorDecl> {
+    code.iter()
+        .filter_map(|decl| match decl {
+            InputDecl::Behavior(b) if b.category.as_deref() == Some(category) => Some(b),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportDecl {
+    pub path: String,
+    pub reexport: bool, // Export Import: importers of this file see the imported behaviors too
+    pub feature: Option<String>, // Import "x.cm" If name: skipped unless the feature is enabled
+    pub symbols: Option<Vec<Ident>>, // Import "x.cm" { A, B }: behaviors x.cm must export
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowDecl {
+    pub name: Ident,
+    pub body: Vec<FlowStmt>,
+    pub export: bool,
+    pub implements: Option<Ident>,
+    pub spans: Vec<crate::span::Span>, // source location per body statement, empty when built in code
+}
+
+use std::fmt;
+
+impl fmt::Display for InputDecl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputDecl::Import(i) => {
+                if i.reexport {
+                    write!(f, "Export ")?;
+                }
+                write!(f, "{}", i.path)?;
+                if let Some(symbols) = &i.symbols {
+                    write!(f, " {{ {} }}", symbols.join(", "))?;
+                }
+                if let Some(feature) = &i.feature {
+                    write!(f, " If {}", feature)?;
+                }
+                Ok(())
+            }
+            InputDecl::Behavior(b) => write!(f, "{:?}", b),
+            InputDecl::Flow(flow) => write!(f, "{:?}", flow),
+        }
+    }
+}
+
+impl fmt::Display for BehaviorDecl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args: Vec<String> = self
+            .inputs
+            .iter()
+            .zip(self.defaults.iter())
+            .map(|(sig, default)| match default {
+                Some(d) => format!("{:?} = {}", sig, d),
+                None => format!("{:?}", sig),
+            })
+            .collect();
         writeln!(
             f,
             "Behavior {}({}) -> {:?}",
@@ -104,7 +453,14 @@ impl fmt::Display for BehaviorDecl {
 
 impl fmt::Display for FlowDecl {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "Flow {} {{", self.name)?;
+        if self.export {
+            write!(f, "Export ")?;
+        }
+        write!(f, "Flow {}", self.name)?;
+        if let Some(b) = &self.implements {
+            write!(f, " Implements {}", b)?;
+        }
+        writeln!(f, " {{")?;
         for stmt in &self.body {
             writeln!(f, "    {}", stmt)?;
         }
@@ -0,0 +1,273 @@
+// CIR (comet intermediate representation) is a versioned JSON form of a Network, so that
+// other tools can read networks without linking the parser.
+//
+// {
+//   "version": 1,
+//   "root": 2,
+//   "nodes": [
+//     { "kind": "literal", "value": { "String": "volume" }, "children": [] },
+//     { "kind": "operator", "name": "data", "children": [0] },
+//     { "kind": "behavior", "decl": { "name": "Mix", "inputs": ["DataFrame"], ... }, "children": [1] }
+//   ]
+// }
+//
+// Node ids are indices into "nodes". Signal types are written by name ("DataFrame", "Int", ...).
+// The version is bumped whenever a field changes meaning or is removed.
+use crate::ast::{Network, Node, NodeType};
+use crate::behavior::{BehaviorDecl, signal_type, signal_type_name};
+use crate::expr::Literal;
+use crate::parser::ParserError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use stdlib::OperatorSpec;
+use stdlib::types::Signal;
+
+pub const CIR_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Cir {
+    version: u32,
+    root: usize,
+    nodes: Vec<CirNode>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum CirNode {
+    Operator {
+        name: String,
+        children: Vec<usize>,
+    },
+    Literal {
+        value: Literal,
+        children: Vec<usize>,
+    },
+    Behavior {
+        decl: Box<BehaviorDecl>,
+        children: Vec<usize>,
+    },
+}
+
+impl Network {
+    pub fn to_cir(&self) -> String {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let children = node.children.clone();
+                match &node.node_type {
+                    NodeType::Operator(op) => CirNode::Operator {
+                        name: op.name.to_string(),
+                        children,
+                    },
+                    NodeType::Literal(lit) => CirNode::Literal {
+                        value: lit.clone(),
+                        children,
+                    },
+                    NodeType::Behavior(b) => CirNode::Behavior {
+                        decl: Box::new(b.clone()),
+                        children,
+                    },
+                }
+            })
+            .collect();
+        let cir = Cir {
+            version: CIR_VERSION,
+            root: self.root,
+            nodes,
+        };
+        serde_json::to_string_pretty(&cir).expect("Network is always serializable")
+    }
+
+    pub fn from_cir(src: &str) -> Result<Network, ParserError> {
+        let cir: Cir =
+            serde_json::from_str(src).map_err(|e| ParserError::InvalidCir(e.to_string()))?;
+        if cir.version != CIR_VERSION {
+            return Err(ParserError::InvalidCir(format!(
+                "Unsupported version {} (expected {})",
+                cir.version, CIR_VERSION
+            )));
+        }
+        let len = cir.nodes.len();
+        if len > 0 && cir.root >= len {
+            return Err(ParserError::InvalidCir(format!(
+                "Root {} out of range",
+                cir.root
+            )));
+        }
+
+        let mut nodes = Vec::with_capacity(len);
+        for node in cir.nodes {
+            let (node_type, children) = match node {
+                CirNode::Operator { name, children } => {
+                    let spec: &'static OperatorSpec = OperatorSpec::lookup(&name).ok_or(
+                        ParserError::InvalidCir(format!("Unknown operator {}", name)),
+                    )?;
+                    (NodeType::Operator(spec), children)
+                }
+                CirNode::Literal { value, children } => (NodeType::Literal(value), children),
+                CirNode::Behavior { decl, children } => (NodeType::Behavior(*decl), children),
+            };
+            if let Some(c) = children.iter().find(|&&c| c >= len) {
+                return Err(ParserError::InvalidCir(format!("Child {} out of range", c)));
+            }
+            nodes.push(Node {
+                node_type,
+                children,
+            });
+        }
+        // Evaluation recurses into children, so a cycle would never terminate.
+        // Depth first search, 1 marks a node on the current path and 2 a finished one.
+        let mut state = vec![0u8; len];
+        for start in 0..len {
+            if state[start] != 0 {
+                continue;
+            }
+            state[start] = 1;
+            let mut stack = vec![(start, 0)];
+            while let Some((idx, next)) = stack.pop() {
+                match nodes[idx].children.get(next) {
+                    Some(&c) => {
+                        stack.push((idx, next + 1));
+                        match state[c] {
+                            0 => {
+                                state[c] = 1;
+                                stack.push((c, 0));
+                            }
+                            1 => {
+                                return Err(ParserError::InvalidCir(format!(
+                                    "Cycle through node {}",
+                                    c
+                                )));
+                            }
+                            _ => {}
+                        }
+                    }
+                    None => state[idx] = 2,
+                }
+            }
+        }
+        // A source must name its data, or inputs() and the runtime cannot resolve it.
+        for (idx, node) in nodes.iter().enumerate() {
+            if matches!(node.node_type, NodeType::Operator(op) if op.name == "data") {
+                let named = match node.children[..] {
+                    [c] => matches!(nodes[c].node_type, NodeType::Literal(Literal::String(_))),
+                    _ => false,
+                };
+                if !named {
+                    return Err(ParserError::InvalidCir(format!(
+                        "Source node {} is not data(\"name\")",
+                        idx
+                    )));
+                }
+            }
+        }
+        Ok(Network {
+            nodes,
+            root: cir.root,
+        })
+    }
+}
+
+// Serde adapters writing signals by type name, for BehaviorDecl fields.
+pub(crate) mod signal {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(sig: &Signal, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(signal_type_name(sig))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Signal, D::Error> {
+        let name = String::deserialize(d)?;
+        signal_type(&name)
+            .ok_or_else(|| serde::de::Error::custom(format!("Unknown type: {}", name)))
+    }
+}
+
+pub(crate) mod signal_list {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(sigs: &[Signal], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(sigs.iter().map(signal_type_name))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Signal>, D::Error> {
+        Vec::<String>::deserialize(d)?
+            .iter()
+            .map(|name| {
+                signal_type(name)
+                    .ok_or_else(|| serde::de::Error::custom(format!("Unknown type: {}", name)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cir_round_trip() {
+        let (network, _) = crate::parser::parse(
+            r#"
+            Behavior Mix(a: DataFrame, window: Int = 5) {
+                operators = [add, ts_mean], integers = [5, 21], floats = [0.5], strings = [],
+                priority = 2
+            } -> DataFrame
+
+            Flow f {
+                volume = data("volume")
+                divide(Mix(volume), ts_mean(volume, 21d)) ^ 0.5
+            }
+            "#,
+        )
+        .unwrap();
+
+        let cir = network.to_cir();
+        assert!(cir.contains("\"version\": 1"));
+        let restored = Network::from_cir(&cir).unwrap();
+        assert_eq!(restored, network);
+        assert_eq!(restored.to_sexpr(), network.to_sexpr());
+
+        let newer = cir.replacen("\"version\": 1", "\"version\": 2", 1);
+        assert!(matches!(
+            Network::from_cir(&newer),
+            Err(ParserError::InvalidCir(_))
+        ));
+    }
+
+    #[test]
+    fn test_cir_unnamed_source() {
+        let (network, _) = crate::parser::parse(r#"Flow f { rank(data("close")) }"#).unwrap();
+        let cir = network.to_cir();
+        assert!(Network::from_cir(&cir).is_ok());
+
+        // data(20) instead of data("close")
+        let bogus = cir.replacen("\"String\": \"close\"", "\"Integer\": 20", 1);
+        assert_ne!(bogus, cir);
+        assert!(matches!(
+            Network::from_cir(&bogus),
+            Err(ParserError::InvalidCir(msg)) if msg.contains("Source node")
+        ));
+    }
+
+    #[test]
+    fn test_cir_cycle() {
+        let self_loop = r#"{ "version": 1, "root": 0, "nodes": [
+            { "kind": "operator", "name": "rank", "children": [0] }
+        ] }"#;
+        assert!(matches!(
+            Network::from_cir(self_loop),
+            Err(ParserError::InvalidCir(msg)) if msg.contains("Cycle")
+        ));
+
+        // rank(flip(...)) pointing back at the rank
+        let back_edge = r#"{ "version": 1, "root": 1, "nodes": [
+            { "kind": "operator", "name": "flip", "children": [1] },
+            { "kind": "operator", "name": "rank", "children": [0] }
+        ] }"#;
+        assert!(matches!(
+            Network::from_cir(back_edge),
+            Err(ParserError::InvalidCir(msg)) if msg.contains("Cycle")
+        ));
+    }
+}
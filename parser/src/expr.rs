@@ -1,12 +1,19 @@
+use serde::{Deserialize, Serialize};
+use stdlib::types::Signal;
+
 pub type Ident = String;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum FlowStmt {
-    Assignment { target: Ident, expr: Expr },
+    Assignment {
+        target: Ident,
+        ty: Option<Signal>, // x: DataFrame = ... asserts the type of the assigned value
+        expr: Expr,
+    },
     Expr(Expr),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Expr {
     Literal(Literal),
     Identifier(Ident),
@@ -20,11 +27,16 @@ pub enum Expr {
         step: Option<Box<Expr>>,
         end: Box<Expr>,
     },
+    Lambda {
+        params: Vec<Ident>,
+        body: Box<Expr>,
+    },
 } // Question : do we allow expressions in list like [ multiply(1,2), 3 ]? should we allow or not?
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Literal {
     Integer(i64),
+    Days(i64), // 21d, a day-count window
     Float(f64),
     String(String),
     Boolean(bool),
@@ -33,7 +45,7 @@ pub enum Literal {
 impl Into<(f64, f64, f64)> for &Literal {
     fn into(self) -> (f64, f64, f64) {
         match self {
-            Literal::Integer(x) => (*x as f64, 0.0, 0.0),
+            Literal::Integer(x) | Literal::Days(x) => (*x as f64, 0.0, 0.0),
             Literal::Float(x) => (0.0, *x, 0.0),
             Literal::String(_) => (0.0, 0.0, 1.0),
             Literal::Boolean(_) => (0.0, 0.0, 2.0),
@@ -63,6 +75,10 @@ impl Hash for Literal {
                 3_u8.hash(state);
                 b.hash(state);
             }
+            Literal::Days(d) => {
+                4_u8.hash(state);
+                d.hash(state);
+            }
         }
     }
 }
@@ -72,7 +88,18 @@ use std::fmt;
 impl fmt::Display for FlowStmt {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            FlowStmt::Assignment { target, expr } => write!(f, "{} = {}", target, expr),
+            FlowStmt::Assignment {
+                target,
+                ty: Some(ty),
+                expr,
+            } => write!(
+                f,
+                "{}: {} = {}",
+                target,
+                crate::behavior::signal_type_name(ty),
+                expr
+            ),
+            FlowStmt::Assignment { target, expr, .. } => write!(f, "{} = {}", target, expr),
             FlowStmt::Expr(e) => write!(f, "{}", e),
         }
     }
@@ -98,6 +125,13 @@ impl fmt::Display for Expr {
                     write!(f, "[{}..{}]", start, end)
                 }
             }
+            Expr::Lambda { params, body } => {
+                if params.len() == 1 {
+                    write!(f, "{} => {}", params[0], body)
+                } else {
+                    write!(f, "({}) => {}", params.join(", "), body)
+                }
+            }
         }
     }
 }
@@ -106,6 +140,7 @@ impl fmt::Display for Literal {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Literal::Integer(i) => write!(f, "{}", i),
+            Literal::Days(d) => write!(f, "{}d", d),
             Literal::Float(fl) => write!(f, "{:?}", fl),
             Literal::String(s) => write!(f, "\"{}\"", s),
             Literal::Boolean(b) => write!(f, "{}", b),
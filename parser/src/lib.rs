@@ -1,4 +1,8 @@
 pub mod ast;
 pub mod behavior;
+pub mod cir;
 pub mod expr;
 pub mod parser;
+pub mod span;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
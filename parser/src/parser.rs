@@ -1,11 +1,13 @@
 use crate::ast::{Network, NodeType};
+use crate::span::{Diagnostic, Span};
 use crate::{
     behavior::*,
-    expr::{Expr, FlowStmt},
+    expr::{Expr, FlowStmt, Ident},
 };
 use pest::Parser;
 use pest_derive::Parser;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use stdlib::OperatorSpec;
 use stdlib::types::Signal;
 use thiserror::Error;
@@ -24,81 +26,1097 @@ pub enum ParserError {
     MissingToken,
     #[error("Semantic error: {0}")]
     SemanticError(String),
+    #[error("Conflicting import: {symbol} is defined in {path_a:?} and {path_b:?}")]
+    ConflictingImport {
+        symbol: String,
+        path_a: PathBuf,
+        path_b: PathBuf,
+    },
+    #[error("Import {module:?} does not export {symbol}")]
+    MissingImportedSymbol { module: String, symbol: String },
+    #[error("File not found: {path:?}")]
+    FileNotFound { path: PathBuf },
+    #[error("Import not found: {requested} (searched {searched:?})")]
+    ImportNotFound {
+        requested: String,
+        searched: Vec<PathBuf>,
+    },
+    #[error("Failed to parse import {path:?}: {source}")]
+    ImportParseFailed {
+        path: PathBuf,
+        source: Box<ParserError>,
+    },
+    #[error("Invalid CIR: {0}")]
+    InvalidCir(String),
+    #[error("Unknown type {ty} for parameter {param} of {func}")]
+    UnknownParamType {
+        func: String,
+        param: String,
+        ty: String,
+    },
+    #[error("Variable {var} is used before it is defined (statement {stmt_index})")]
+    UseBeforeDefinition { var: String, stmt_index: usize },
+    #[error("Unknown operators: {}", .0.join(", "))]
+    UnknownOperators(Vec<String>),
+    #[error("Recursive implementation: {}", chain.join(" -> "))]
+    RecursiveImpl { chain: Vec<String> },
+    #[error("Incompatible redefinition of {name}: {sig_a} and {sig_b}")]
+    IncompatibleRedefinition {
+        name: String,
+        sig_a: String,
+        sig_b: String,
+    },
+    #[error("Variable {var} is annotated {expected} but assigned a {found}")]
+    AnnotationMismatch {
+        var: String,
+        expected: String,
+        found: String,
+        stmt_index: usize,
+    },
+    #[error("Source {0} is not allowed")]
+    ForbiddenSource(String),
+    #[error("Warnings denied: {}", .0.iter().map(|w| w.to_string()).collect::<Vec<_>>().join("; "))]
+    DeniedWarnings(Vec<Warning>),
+}
+
+// Problems that do not stop the analysis.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    UnusedVariable {
+        flow: String,
+        var: String,
+    },
+    UnusedExpression {
+        flow: String,
+        stmt_index: usize,
+    },
+    ShadowedVariable {
+        flow: String,
+        name: String,
+        stmt_index: usize,
+    },
+    DeprecatedBehavior {
+        name: String,
+        reason: String,
+    },
+    UnreachableImpl {
+        name: String,
+    },
+    OrphanNodes {
+        expr: String,
+        nodes: Vec<usize>,
+    },
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::UnusedVariable { flow, var } => {
+                write!(
+                    f,
+                    "Variable {} is assigned but never used in flow {}",
+                    var, flow
+                )
+            }
+            Warning::UnusedExpression { flow, stmt_index } => write!(
+                f,
+                "Result of statement {} is discarded in flow {}",
+                stmt_index, flow
+            ),
+            Warning::ShadowedVariable {
+                flow,
+                name,
+                stmt_index,
+            } => write!(
+                f,
+                "Variable {} is assigned again by statement {} in flow {}",
+                name, stmt_index, flow
+            ),
+            Warning::DeprecatedBehavior { name, reason } => {
+                write!(f, "Behavior {} is deprecated: {}", name, reason)
+            }
+            Warning::UnreachableImpl { name } => write!(
+                f,
+                "Flow {} is never used: the output flow does not reach its behavior",
+                name
+            ),
+            Warning::OrphanNodes { expr, nodes } => write!(
+                f,
+                "{} nodes built for {} are not connected to its result",
+                nodes.len(),
+                expr
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Analysis {
+    pub network: Network,
+    pub behavior_nodes: Vec<usize>,
+    pub warnings: Vec<Warning>,
 }
 
 pub fn parse(input: &str) -> Result<(Network, Vec<usize>), ParserError> {
     // Parses Flow and behavior.
-    let mut pairs = CometParser::parse(Rule::program, input)?;
-    let program_pair = pairs.next().ok_or(ParserError::MissingToken)?;
-    let code: InputCode = parse_program(program_pair)?;
-    let mut flow_opt = None;
-    let behaviors: Vec<BehaviorDecl> = code
+    let code: InputCode = parse_source(input)?;
+    // Imports are not resolved yet, so they are dropped before analysis.
+    let code: InputCode = code
         .into_iter()
-        .filter_map(|decl| match decl {
-            InputDecl::Import(_) => None,
-            InputDecl::Behavior(b) => Some(b),
-            InputDecl::Flow(f) => {
-                flow_opt = Some(f);
-                None
-            }
-        })
+        .filter(|decl| !matches!(decl, InputDecl::Import(_)))
         .collect();
-    let flow = flow_opt.ok_or(ParserError::MissingToken)?;
+    analyze_program(code)
+}
 
-    // Locates assignments in the flow's body. Convert them into AST(Programs)
-    let mut assignments = Vec::new();
-    let mut output = None;
+pub fn parse_file(path: &Path) -> Result<(Network, Vec<usize>), ParserError> {
+    analyze_program(load_program(path)?)
+}
 
-    for stmt in flow.body.iter() {
-        match stmt {
-            FlowStmt::Assignment { target, expr } => {
-                assignments.push((target.clone(), expr.clone()));
+pub fn analyze_file(path: &Path, options: &AnalyzeOptions) -> Result<Analysis, ParserError> {
+    analyze_program_with_options(load_program(path)?, &HashMap::new(), options)
+}
+
+pub fn load_program(path: &Path) -> Result<InputCode, ParserError> {
+    load_program_with_features(path, &HashSet::new())
+}
+
+// Imports guarded by a feature (Import "x.cm" If name) are only followed for enabled features.
+pub fn load_program_with_features(
+    path: &Path,
+    features: &HashSet<String>,
+) -> Result<InputCode, ParserError> {
+    // Parses a file and the behaviors visible from it through its imports.
+    let mut loaded = HashMap::new();
+    let mut code = Vec::new();
+    if !path.is_file() {
+        return Err(ParserError::FileNotFound {
+            path: path.to_path_buf(),
+        });
+    }
+    load_file(path, true, features, &mut loaded, &mut code)?;
+    Ok(code)
+}
+
+// Like load_program, for a source that is not a file, e.g. standard input.
+// Relative imports resolve from base_dir, usually the current working directory.
+pub fn load_program_from_reader(
+    mut reader: impl std::io::Read,
+    base_dir: &Path,
+    features: &HashSet<String>,
+) -> Result<InputCode, ParserError> {
+    let mut src = String::new();
+    reader
+        .read_to_string(&mut src)
+        .map_err(|e| ParserError::SemanticError(format!("Failed to read source: {}", e)))?;
+    let decls = parse_source(&src)?;
+    let mut loaded = HashMap::new();
+    let mut code = Vec::new();
+    load_decls(
+        decls,
+        &base_dir.join("<stdin>"),
+        base_dir,
+        true,
+        features,
+        &mut loaded,
+        &mut code,
+    )?;
+    Ok(code)
+}
+
+// The file and every file it imports, transitively, sorted. Guarded imports are followed for the
+// enabled features only.
+pub fn source_files(path: &Path, features: &HashSet<String>) -> Result<Vec<PathBuf>, ParserError> {
+    let mut loaded = HashMap::new();
+    let mut code = Vec::new();
+    if !path.is_file() {
+        return Err(ParserError::FileNotFound {
+            path: path.to_path_buf(),
+        });
+    }
+    load_file(path, true, features, &mut loaded, &mut code)?;
+    let mut files: Vec<PathBuf> = loaded.into_keys().collect();
+    files.sort();
+    Ok(files)
+}
+
+// Imports are resolved relative to the importing file.
+fn resolve_import(requested: &str, base_dir: &Path) -> Result<PathBuf, ParserError> {
+    let searched = vec![base_dir.to_path_buf()];
+    searched
+        .iter()
+        .map(|dir| dir.join(requested))
+        .find(|p| p.is_file())
+        .ok_or(ParserError::ImportNotFound {
+            requested: requested.to_string(),
+            searched,
+        })
+}
+
+// Behaviors paired with the file defining them.
+type ModuleSymbols = Vec<(PathBuf, BehaviorDecl)>;
+
+// Adds a behavior to the symbols of a module. Returns false when it is already there.
+fn add_symbol(
+    symbols: &mut ModuleSymbols,
+    origin: &Path,
+    b: &BehaviorDecl,
+) -> Result<bool, ParserError> {
+    for (prev_origin, prev) in symbols.iter() {
+        if prev.name != b.name {
+            continue;
+        }
+        if prev == b {
+            // The same definition reached through several imports.
+            return Ok(false);
+        }
+        if prev_origin == origin {
+            continue;
+        }
+        let symbol = b.name.clone().unwrap_or_default();
+        if prev.signature() != b.signature() {
+            return Err(ParserError::IncompatibleRedefinition {
+                name: symbol,
+                sig_a: prev.signature(),
+                sig_b: b.signature(),
+            });
+        }
+        return Err(ParserError::ConflictingImport {
+            symbol,
+            path_a: prev_origin.clone(),
+            path_b: origin.to_path_buf(),
+        });
+    }
+    symbols.push((origin.to_path_buf(), b.clone()));
+    Ok(true)
+}
+
+// Loads a file and returns the behaviors it exports: its own and those of its re-exported imports.
+// A plain import is only visible to the importing file. None for a file that is still being
+// loaded, imported again through an import cycle.
+fn load_file(
+    path: &Path,
+    is_root: bool,
+    features: &HashSet<String>,
+    loaded: &mut HashMap<PathBuf, Option<ModuleSymbols>>, // file -> exported behaviors
+    code: &mut InputCode,
+) -> Result<Option<ModuleSymbols>, ParserError> {
+    let path = path
+        .canonicalize()
+        .map_err(|e| ParserError::SemanticError(format!("Failed to import {:?}: {}", path, e)))?;
+    if let Some(exports) = loaded.get(&path) {
+        return Ok(exports.clone());
+    }
+    loaded.insert(path.clone(), None);
+    let src = std::fs::read_to_string(&path)
+        .map_err(|e| ParserError::SemanticError(format!("Failed to import {:?}: {}", path, e)))?;
+    let decls = parse_source(&src).map_err(|e| {
+        if is_root {
+            e
+        } else {
+            ParserError::ImportParseFailed {
+                path: path.clone(),
+                source: Box::new(e),
+            }
+        }
+    })?;
+    let base_dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let exports = load_decls(decls, &path, &base_dir, is_root, features, loaded, code)?;
+    loaded.insert(path, Some(exports.clone()));
+    Ok(Some(exports))
+}
+
+// Resolves the imports of parsed declarations and returns the behaviors they export.
+fn load_decls(
+    decls: InputCode,
+    path: &Path,
+    base_dir: &Path,
+    is_root: bool,
+    features: &HashSet<String>,
+    loaded: &mut HashMap<PathBuf, Option<ModuleSymbols>>,
+    code: &mut InputCode,
+) -> Result<ModuleSymbols, ParserError> {
+    let mut visible = Vec::new();
+    let mut exports = Vec::new();
+    for decl in decls {
+        match decl {
+            InputDecl::Import(import)
+                if import
+                    .feature
+                    .as_ref()
+                    .is_some_and(|f| !features.contains(f)) => {}
+            InputDecl::Import(import) => {
+                let resolved = resolve_import(&import.path, base_dir)?;
+                // Through a cycle nothing is exported yet, so there is nothing to check either.
+                let Some(imported) = load_file(&resolved, false, features, loaded, code)? else {
+                    continue;
+                };
+                for symbol in import.symbols.iter().flatten() {
+                    if !imported
+                        .iter()
+                        .any(|(_, b)| b.name.as_ref() == Some(symbol))
+                    {
+                        return Err(ParserError::MissingImportedSymbol {
+                            module: import.path.clone(),
+                            symbol: symbol.clone(),
+                        });
+                    }
+                }
+                // { A, B } imports only the behaviors listed.
+                let listed = |b: &BehaviorDecl| match &import.symbols {
+                    Some(symbols) => symbols.iter().any(|s| b.name.as_ref() == Some(s)),
+                    None => true,
+                };
+                for (origin, b) in imported.into_iter().filter(|(_, b)| listed(b)) {
+                    if add_symbol(&mut visible, &origin, &b)? && is_root {
+                        code.push(InputDecl::Behavior(b.clone()));
+                    }
+                    if import.reexport {
+                        add_symbol(&mut exports, &origin, &b)?;
+                    }
+                }
+            }
+            InputDecl::Behavior(b) => {
+                add_symbol(&mut exports, path, &b)?;
+                if add_symbol(&mut visible, path, &b)? && is_root {
+                    code.push(InputDecl::Behavior(b));
+                }
             }
-            FlowStmt::Expr(expr) => {
-                output = Some(expr.clone());
-                break; // Stop loop after the first Expr is found
+            // Imported files only contribute behaviors.
+            InputDecl::Flow(f) if is_root => code.push(InputDecl::Flow(f)),
+            InputDecl::Flow(_) => {}
+        }
+    }
+    Ok(exports)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AnalyzeOptions {
+    // Identifiers that no statement assigns are read as data("name") instead of rejected.
+    // For sources provided by the host runtime that are not declared in comet.
+    pub allow_undeclared_sources: bool,
+    // When set, only these data sources may be read. None allows every source.
+    pub allowed_sources: Option<HashSet<String>>,
+    // Reassigning a variable is intended, so it is not reported.
+    pub allow_shadowing: bool,
+    // Any warning fails the analysis with DeniedWarnings, e.g. to gate CI.
+    pub deny_warnings: bool,
+    // After each expression is built, checks that every node built for it is reachable from
+    // its result and warns otherwise. A validation mode for changes to build_ast.
+    pub check_orphans: bool,
+}
+
+pub fn analyze_program(code: InputCode) -> Result<(Network, Vec<usize>), ParserError> {
+    analyze_program_with(code, &HashMap::new())
+}
+
+pub fn analyze_program_with(
+    code: InputCode,
+    seeds: &HashMap<Ident, Expr>, // variables bound before the flow body, e.g. prices = data("close")
+) -> Result<(Network, Vec<usize>), ParserError> {
+    let analysis = analyze_program_with_options(code, seeds, &AnalyzeOptions::default())?;
+    Ok((analysis.network, analysis.behavior_nodes))
+}
+
+pub fn analyze_program_with_options(
+    code: InputCode,
+    seeds: &HashMap<Ident, Expr>,
+    options: &AnalyzeOptions,
+) -> Result<Analysis, ParserError> {
+    analyze_program_located(code, seeds, options).map_err(|d| *d.error)
+}
+
+// Like analyze_program_with_options, but errors raised while checking or building a flow carry
+// the span of the statement they were raised for.
+pub fn analyze_program_located(
+    code: InputCode,
+    seeds: &HashMap<Ident, Expr>,
+    options: &AnalyzeOptions,
+) -> Result<Analysis, Diagnostic> {
+    // Builds the network from declarations that are already in memory (no file I/O).
+    let mut flows: Vec<FlowDecl> = Vec::new();
+    let mut behaviors: Vec<BehaviorDecl> = Vec::new();
+    for decl in code {
+        match decl {
+            InputDecl::Import(import) => {
+                return Err(ParserError::SemanticError(format!(
+                    "Import is not supported for in-memory programs: {}",
+                    import.path
+                ))
+                .into());
             }
+            InputDecl::Behavior(b) => behaviors.push(b),
+            InputDecl::Flow(f) => flows.push(f),
         }
     }
 
-    let assignments_map: HashMap<&str, &Expr> =
-        assignments.iter().map(|(k, v)| (k.as_str(), v)).collect();
+    let flow = output_flow(&flows)?.clone();
+
     let mut behaviors_map: HashMap<&str, &BehaviorDecl> = HashMap::new();
     for b in &behaviors {
         behaviors_map.insert(b.name.as_ref().unwrap().as_str(), b);
     }
 
-    let out_expr = output.ok_or(ParserError::SemanticError(
-        "No output expression in flow".into(),
-    ))?;
-    let mut behaviors_ref: Vec<usize> = Vec::new();
+    // Nullary behaviors may be used by name alone, like a variable.
+    let nullary: Vec<&str> = behaviors
+        .iter()
+        .filter(|b| b.is_nullary())
+        .map(|b| b.name.as_ref().unwrap().as_str())
+        .collect();
+
+    // Flows implementing a behavior replace calls to that behavior.
+    let mut impl_flows: HashMap<&str, &FlowDecl> = HashMap::new();
+    for f in &flows {
+        if let Some(b) = &f.implements {
+            match behaviors_map.get(b.as_str()) {
+                Some(decl) if decl.inputs.is_empty() => {
+                    check_definitions(f, &nullary, options.allow_undeclared_sources)
+                        .map_err(|e| locate(e, f))?;
+                    check_annotations(f, &behaviors_map).map_err(|e| locate(e, f))?;
+                    impl_flows.insert(b.as_str(), f);
+                }
+                Some(_) => {
+                    return Err(ParserError::SemanticError(format!(
+                        "Flow {} cannot implement behavior {} with inputs",
+                        f.name, b
+                    ))
+                    .into());
+                }
+                None => {
+                    return Err(ParserError::SemanticError(format!(
+                        "Flow {} implements unknown behavior {}",
+                        f.name, b
+                    ))
+                    .into());
+                }
+            }
+        }
+    }
+
+    // Bodies are prepared once, callees before callers, and reused by every call.
+    let mut impl_bodies: HashMap<&str, FlowBody> = HashMap::new();
+    for name in impl_order(&impl_flows)? {
+        impl_bodies.insert(name, flow_body(impl_flows[name])?);
+    }
+
+    let seed_names: Vec<&str> = seeds
+        .keys()
+        .map(|k| k.as_str())
+        .chain(nullary.iter().copied())
+        .collect();
+    check_definitions(&flow, &seed_names, options.allow_undeclared_sources)
+        .map_err(|e| locate(e, &flow))?;
+    check_annotations(&flow, &behaviors_map).map_err(|e| locate(e, &flow))?;
+    let (assignments, out_expr) = flow_body(&flow)?;
+    // Assignments in the flow shadow seeds of the same name.
+    let assignments_map: HashMap<&str, &Expr> = seeds
+        .iter()
+        .chain(assignments.iter().map(|(k, v)| (k, v)))
+        .map(|(k, v)| (k.as_str(), v))
+        .collect();
+    let mut ctx = BuildContext {
+        behaviors: &behaviors_map,
+        impl_bodies: &impl_bodies,
+        behaviors_ptr: Vec::new(),
+        expanding: Vec::new(),
+        allow_undeclared_sources: options.allow_undeclared_sources,
+        unknown_ops: Vec::new(),
+        deprecated: Vec::new(),
+        inlined: HashSet::new(),
+        failed_at: None,
+        check_orphans: options.check_orphans,
+        orphans: Vec::new(),
+    };
 
     let mut network = Network::new();
-    let root = build_ast(
-        &mut network,
-        &out_expr,
-        &assignments_map,
-        &behaviors_map,
-        &mut behaviors_ref,
-    )?;
+    let root =
+        build_ast(&mut network, &out_expr, &assignments_map, &mut ctx, false).map_err(|e| {
+            let (behavior, var) = ctx.failed_at.take().unwrap_or((None, None));
+            let f = behavior.map_or(&flow, |b| impl_flows[b.as_str()]);
+            let stmt_index = var
+                .and_then(|v| {
+                    f.body.iter().rposition(
+                        |stmt| matches!(stmt, FlowStmt::Assignment { target, .. } if *target == v),
+                    )
+                })
+                .unwrap_or(f.body.len().saturating_sub(1));
+            Diagnostic {
+                error: Box::new(e),
+                span: f.spans.get(stmt_index).copied(),
+            }
+        })?;
     network.root = root;
+    if !ctx.unknown_ops.is_empty() {
+        return Err(ParserError::UnknownOperators(ctx.unknown_ops).into());
+    }
+    if let Some(allowed) = &options.allowed_sources {
+        for input in network.inputs() {
+            if let crate::ast::Input::Source { name, .. } = input
+                && !allowed.contains(&name)
+            {
+                return Err(ParserError::ForbiddenSource(name).into());
+            }
+        }
+        // A behavior searching with data can load any of its strings as a source.
+        for &idx in &ctx.behaviors_ptr {
+            let NodeType::Behavior(b) = &network.nodes[idx].node_type else {
+                unreachable!()
+            };
+            let operators = b.operators.as_deref().unwrap_or_default();
+            if !operators.iter().any(|op| op == "data") {
+                continue;
+            }
+            for name in b.strings.as_deref().unwrap_or_default() {
+                if !allowed.contains(name) {
+                    return Err(ParserError::ForbiddenSource(name.clone()).into());
+                }
+            }
+        }
+    }
+    let mut behaviors_ref = ctx.behaviors_ptr;
+
+    // Higher priority behaviors come first. Ties keep the order of appearance.
+    behaviors_ref.sort_by_key(|&idx| match &network.nodes[idx].node_type {
+        NodeType::Behavior(b) => std::cmp::Reverse(b.priority.unwrap_or(0)),
+        _ => unreachable!(),
+    });
+
+    // Implementing flows are reported in declaration order, the output flow last.
+    let mut warnings = Vec::new();
+    for f in &flows {
+        if f.implements.is_some() {
+            warnings.extend(flow_warnings(f, options));
+        }
+    }
+    for f in &flows {
+        if let Some(b) = &f.implements
+            && f.name != flow.name
+            && !ctx.inlined.contains(b)
+        {
+            warnings.push(Warning::UnreachableImpl {
+                name: f.name.clone(),
+            });
+        }
+    }
+    warnings.extend(flow_warnings(&flow, options));
+    warnings.extend(
+        ctx.orphans
+            .into_iter()
+            .map(|(expr, nodes)| Warning::OrphanNodes { expr, nodes }),
+    );
+    for name in &ctx.deprecated {
+        warnings.push(Warning::DeprecatedBehavior {
+            name: name.clone(),
+            reason: behaviors_map[name.as_str()].deprecated.clone().unwrap(),
+        });
+    }
+    if options.deny_warnings && !warnings.is_empty() {
+        return Err(ParserError::DeniedWarnings(warnings).into());
+    }
 
     // full ast (operator nodes and literals), reference to behavior node (undetermined node)
-    Ok((network, behaviors_ref))
+    Ok(Analysis {
+        network,
+        behavior_nodes: behaviors_ref,
+        warnings,
+    })
+}
+
+// When a flow is exported, helper flows are ignored. Otherwise the last flow is used.
+fn output_flow(flows: &[FlowDecl]) -> Result<&FlowDecl, ParserError> {
+    let exported: Vec<&FlowDecl> = flows.iter().filter(|f| f.export).collect();
+    if exported.len() > 1 {
+        return Err(ParserError::SemanticError(format!(
+            "Multiple exported flows: {}",
+            exported
+                .iter()
+                .map(|f| f.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+    exported
+        .first()
+        .copied()
+        .or(flows.iter().rev().find(|f| f.implements.is_none()))
+        .ok_or(ParserError::MissingToken)
+}
+
+// Human readable account of how the network is built from the declarations:
+// which flow is the output and why the others are not, the statements of the output flow,
+// and the search space of every behavior left in the network.
+pub fn explain(code: InputCode) -> Result<String, ParserError> {
+    let flows: Vec<FlowDecl> = code
+        .iter()
+        .filter_map(|decl| match decl {
+            InputDecl::Flow(f) => Some(f.clone()),
+            _ => None,
+        })
+        .collect();
+    let flow = output_flow(&flows)?;
+
+    let mut lines = Vec::new();
+    let reason = if flow.export { "exported" } else { "last flow" };
+    lines.push(format!("Output flow: {} ({})", flow.name, reason));
+    for f in flows.iter().filter(|f| f.name != flow.name) {
+        let reason = match &f.implements {
+            Some(b) => format!("implements {}, inlined where {}() is called", b, b),
+            None if flow.export => format!("{} is exported", flow.name),
+            None => format!("superseded by {}", flow.name),
+        };
+        lines.push(format!("Skipped flow {}: {}", f.name, reason));
+    }
+
+    lines.push(format!("Statements of {}:", flow.name));
+    for (i, stmt) in flow.body.iter().enumerate() {
+        lines.push(format!("  {}: {}", i, stmt));
+    }
+
+    let (network, behavior_nodes) = analyze_program(code)?;
+    for &idx in &behavior_nodes {
+        if let NodeType::Behavior(b) = &network.nodes[idx].node_type {
+            lines.push(format!(
+                "Behavior {} at node {}: {} operators, {} integers, {} floats, {} strings, {} inputs",
+                b.name.as_deref().unwrap_or("_"),
+                idx,
+                b.operators.as_ref().map_or(0, |v| v.len()),
+                b.integers.as_ref().map_or(0, |v| v.len()),
+                b.floats.as_ref().map_or(0, |v| v.len()),
+                b.strings.as_ref().map_or(0, |v| v.len()),
+                b.inputs.len()
+            ));
+        }
+    }
+    lines.push(format!(
+        "Network: {} nodes, {} behaviors to search",
+        network.nodes.len(),
+        behavior_nodes.len()
+    ));
+    Ok(lines.join("\n"))
+}
+
+// Locates assignments in the flow's body. Convert them into AST(Programs)
+// Assignments in order, and the output expression.
+type FlowBody = (Vec<(String, Expr)>, Expr);
+
+fn flow_body(flow: &FlowDecl) -> Result<FlowBody, ParserError> {
+    let mut assignments = Vec::new();
+    for stmt in flow.body.iter() {
+        if let FlowStmt::Assignment { target, expr, .. } = stmt {
+            assignments.push((target.clone(), expr.clone()));
+        }
+    }
+    // The flow's last statement is its output. Earlier bare expressions are not used.
+    match flow.body.last() {
+        Some(FlowStmt::Expr(expr)) => Ok((assignments, expr.clone())),
+        _ => Err(ParserError::SemanticError(format!(
+            "No output expression in flow {}",
+            flow.name
+        ))),
+    }
+}
+
+// Implemented behaviors ordered so that each comes after the implemented behaviors its flow calls.
+// A cycle could never finish inlining, so it is rejected even when the output flow does not reach it.
+fn impl_order<'a>(
+    impl_flows: &HashMap<&'a str, &'a FlowDecl>,
+) -> Result<Vec<&'a str>, ParserError> {
+    fn calls<'e>(expr: &'e Expr, out: &mut Vec<&'e str>) {
+        match expr {
+            Expr::Call { fn_name, args } => {
+                out.push(fn_name);
+                args.iter().for_each(|a| calls(a, out));
+            }
+            Expr::List(items) => items.iter().for_each(|a| calls(a, out)),
+            Expr::Range { start, step, end } => {
+                calls(start, out);
+                if let Some(st) = step {
+                    calls(st, out);
+                }
+                calls(end, out);
+            }
+            Expr::Lambda { body, .. } => calls(body, out),
+            Expr::Identifier(_) | Expr::Literal(_) => {}
+        }
+    }
+    fn visit<'a>(
+        name: &'a str,
+        impl_flows: &HashMap<&'a str, &'a FlowDecl>,
+        path: &mut Vec<&'a str>,
+        order: &mut Vec<&'a str>,
+    ) -> Result<(), ParserError> {
+        if order.contains(&name) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|n| *n == name) {
+            let mut chain: Vec<String> = path[start..].iter().map(|n| n.to_string()).collect();
+            chain.push(name.to_string());
+            return Err(ParserError::RecursiveImpl { chain });
+        }
+        path.push(name);
+        let mut called = Vec::new();
+        for stmt in &impl_flows[name].body {
+            match stmt {
+                FlowStmt::Assignment { expr, .. } | FlowStmt::Expr(expr) => {
+                    calls(expr, &mut called)
+                }
+            }
+        }
+        for callee in called {
+            if let Some((&callee, _)) = impl_flows.get_key_value(callee) {
+                visit(callee, impl_flows, path, order)?;
+            }
+        }
+        path.pop();
+        order.push(name);
+        Ok(())
+    }
+
+    let mut names: Vec<&'a str> = impl_flows.keys().copied().collect();
+    names.sort();
+    let mut order = Vec::new();
+    for name in names {
+        visit(name, impl_flows, &mut Vec::new(), &mut order)?;
+    }
+    Ok(order)
+}
+
+// Variables read by an expression. Lambda parameters are not variables of the flow.
+fn expr_identifiers<'e>(expr: &'e Expr, out: &mut Vec<&'e str>) {
+    match expr {
+        Expr::Identifier(id) => out.push(id),
+        Expr::Call { args, .. } => args.iter().for_each(|a| expr_identifiers(a, out)),
+        Expr::List(items) => items.iter().for_each(|a| expr_identifiers(a, out)),
+        Expr::Range { start, step, end } => {
+            expr_identifiers(start, out);
+            if let Some(st) = step {
+                expr_identifiers(st, out);
+            }
+            expr_identifiers(end, out);
+        }
+        Expr::Lambda { params, body } => {
+            let mut inner = Vec::new();
+            expr_identifiers(body, &mut inner);
+            out.extend(
+                inner
+                    .into_iter()
+                    .filter(|id| !params.iter().any(|p| p == id)),
+            );
+        }
+        Expr::Literal(_) => {}
+    }
+}
+
+// Assignments no later statement reads, bare expressions other than the output, and
+// reassigned variables. `result` is conventionally built up in steps, so it may be reassigned.
+fn flow_warnings(flow: &FlowDecl, options: &AnalyzeOptions) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    let mut assigned = HashSet::new();
+    let last = flow.body.len().saturating_sub(1);
+    for (stmt_index, stmt) in flow.body.iter().enumerate() {
+        match stmt {
+            FlowStmt::Assignment { target, .. } => {
+                if !assigned.insert(target.as_str())
+                    && target != "result"
+                    && !options.allow_shadowing
+                {
+                    warnings.push(Warning::ShadowedVariable {
+                        flow: flow.name.clone(),
+                        name: target.clone(),
+                        stmt_index,
+                    });
+                }
+                let mut used = Vec::new();
+                for later in &flow.body[stmt_index + 1..] {
+                    match later {
+                        FlowStmt::Assignment { expr, .. } | FlowStmt::Expr(expr) => {
+                            expr_identifiers(expr, &mut used)
+                        }
+                    }
+                }
+                if !used.contains(&target.as_str()) {
+                    warnings.push(Warning::UnusedVariable {
+                        flow: flow.name.clone(),
+                        var: target.clone(),
+                    });
+                }
+            }
+            FlowStmt::Expr(_) if stmt_index != last => warnings.push(Warning::UnusedExpression {
+                flow: flow.name.clone(),
+                stmt_index,
+            }),
+            FlowStmt::Expr(_) => {}
+        }
+    }
+    warnings
+}
+
+// Attaches the span of the offending statement of `flow` to errors that name one.
+fn locate(error: ParserError, flow: &FlowDecl) -> Diagnostic {
+    let span = match &error {
+        ParserError::UseBeforeDefinition { stmt_index, .. }
+        | ParserError::AnnotationMismatch { stmt_index, .. } => {
+            flow.spans.get(*stmt_index).copied()
+        }
+        _ => None,
+    };
+    Diagnostic {
+        error: Box::new(error),
+        span,
+    }
+}
+
+// Every statement may only read variables assigned by earlier statements or given as inputs.
+// Without this, x = f(x) would expand x into itself forever.
+// With external sources allowed, only variables the flow assigns somewhere are checked.
+fn check_definitions(
+    flow: &FlowDecl,
+    inputs: &[&str],
+    allow_external: bool,
+) -> Result<(), ParserError> {
+    let assigned: HashSet<&str> = flow
+        .body
+        .iter()
+        .filter_map(|stmt| match stmt {
+            FlowStmt::Assignment { target, .. } => Some(target.as_str()),
+            FlowStmt::Expr(_) => None,
+        })
+        .collect();
+    let mut defined: HashSet<&str> = inputs.iter().copied().collect();
+    for (stmt_index, stmt) in flow.body.iter().enumerate() {
+        let (target, expr) = match stmt {
+            FlowStmt::Assignment { target, expr, .. } => (Some(target), expr),
+            FlowStmt::Expr(expr) => (None, expr),
+        };
+        let mut used = Vec::new();
+        expr_identifiers(expr, &mut used);
+        let is_external = |id: &str| allow_external && !assigned.contains(id);
+        if let Some(var) = used
+            .into_iter()
+            .find(|id| !defined.contains(id) && !is_external(id))
+        {
+            return Err(ParserError::UseBeforeDefinition {
+                var: var.to_string(),
+                stmt_index,
+            });
+        }
+        if let Some(t) = target {
+            defined.insert(t);
+        }
+    }
+    Ok(())
+}
+
+// Annotated assignments must assign a value of the annotated type. Types are inferred from
+// literals, operator and behavior outputs, and earlier variables; a value whose type cannot be
+// inferred (e.g. an undeclared source) takes the annotation on trust.
+fn check_annotations(
+    flow: &FlowDecl,
+    behaviors: &HashMap<&str, &BehaviorDecl>,
+) -> Result<(), ParserError> {
+    fn infer(
+        expr: &Expr,
+        types: &HashMap<&str, Signal>,
+        behaviors: &HashMap<&str, &BehaviorDecl>,
+    ) -> Option<Signal> {
+        use crate::expr::Literal;
+        match expr {
+            Expr::Literal(Literal::Integer(_) | Literal::Days(_)) => Some(Signal::Int(None)),
+            Expr::Literal(Literal::Float(_)) => Some(Signal::Float(None)),
+            Expr::Literal(Literal::String(_)) => Some(Signal::String(None)),
+            Expr::Identifier(id) => types
+                .get(id.as_str())
+                .cloned()
+                .or_else(|| behaviors.get(id.as_str()).map(|b| b.output.clone())),
+            Expr::Call { fn_name, .. } => match behaviors.get(fn_name.as_str()) {
+                Some(b) => Some(b.output.clone()),
+                None => OperatorSpec::lookup(fn_name).map(|spec| spec.output_shape.clone()),
+            },
+            _ => None,
+        }
+    }
+
+    let mut types: HashMap<&str, Signal> = HashMap::new();
+    for (stmt_index, stmt) in flow.body.iter().enumerate() {
+        if let FlowStmt::Assignment { target, ty, expr } = stmt {
+            let inferred = infer(expr, &types, behaviors);
+            match (ty, inferred) {
+                (Some(expected), Some(found))
+                    if std::mem::discriminant(expected) != std::mem::discriminant(&found) =>
+                {
+                    return Err(ParserError::AnnotationMismatch {
+                        var: target.clone(),
+                        expected: signal_type_name(expected).to_string(),
+                        found: signal_type_name(&found).to_string(),
+                        stmt_index,
+                    });
+                }
+                (Some(expected), _) => {
+                    types.insert(target.as_str(), expected.clone());
+                }
+                (None, Some(found)) => {
+                    types.insert(target.as_str(), found);
+                }
+                (None, None) => {
+                    types.remove(target.as_str());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Replaces identifiers bound by a lambda. Lambdas nested in the body shadow their own parameters.
+fn substitute(expr: &Expr, bindings: &HashMap<&str, &Expr>) -> Expr {
+    match expr {
+        Expr::Identifier(id) => bindings
+            .get(id.as_str())
+            .map_or_else(|| expr.clone(), |e| (*e).clone()),
+        Expr::Call { fn_name, args } => Expr::Call {
+            fn_name: fn_name.clone(),
+            args: args.iter().map(|a| substitute(a, bindings)).collect(),
+        },
+        Expr::List(items) => Expr::List(items.iter().map(|a| substitute(a, bindings)).collect()),
+        Expr::Range { start, step, end } => Expr::Range {
+            start: Box::new(substitute(start, bindings)),
+            step: step.as_ref().map(|st| Box::new(substitute(st, bindings))),
+            end: Box::new(substitute(end, bindings)),
+        },
+        Expr::Lambda { params, body } => {
+            let inner: HashMap<&str, &Expr> = bindings
+                .iter()
+                .filter(|(k, _)| !params.iter().any(|p| p == *k))
+                .map(|(k, v)| (*k, *v))
+                .collect();
+            Expr::Lambda {
+                params: params.clone(),
+                body: Box::new(substitute(body, &inner)),
+            }
+        }
+        Expr::Literal(_) => expr.clone(),
+    }
+}
+
+// Operators whose second argument is a divisor. Only a constant divisor is checked: a frame
+// that is zero somewhere is only known when the expression runs.
+const DIVISION_OPERATORS: [&str; 2] = ["divide", "rank_div"];
+
+fn is_zero(lit: &crate::expr::Literal) -> bool {
+    match lit {
+        crate::expr::Literal::Integer(i) => *i == 0,
+        crate::expr::Literal::Float(f) => *f == 0.0,
+        _ => false,
+    }
+}
+
+// State shared across the whole build_ast recursion
+struct BuildContext<'a> {
+    behaviors: &'a HashMap<&'a str, &'a BehaviorDecl>,
+    impl_bodies: &'a HashMap<&'a str, FlowBody>,
+    behaviors_ptr: Vec<usize>,
+    expanding: Vec<String>, // behaviors whose implementing flow is being inlined, outermost first
+    allow_undeclared_sources: bool,
+    unknown_ops: Vec<String>, // calls with no stdlib operator, in order of appearance
+    deprecated: Vec<String>,  // deprecated behaviors called, in order of first use
+    inlined: HashSet<String>, // implemented behaviors expanded at least once
+    // Innermost statement an error was raised for: (implemented behavior, or None for the
+    // output flow; assigned variable, or None for the output expression).
+    failed_at: Option<(Option<String>, Option<String>)>,
+    check_orphans: bool,
+    orphans: Vec<(String, Vec<usize>)>, // expression and the nodes it left unconnected
 }
 
 fn build_ast(
     network: &mut Network,
     output: &Expr,
     assignments: &HashMap<&str, &Expr>,
-    behaviors: &HashMap<&str, &BehaviorDecl>,
-    behaviors_ptr: &mut Vec<usize>,
+    ctx: &mut BuildContext,
+    is_value: bool, // false only for the flow's output expression
+) -> Result<usize, ParserError> {
+    let first = network.nodes.len();
+    let node_id = build_node(network, output, assignments, ctx, is_value)?;
+    if ctx.check_orphans {
+        report_orphans(network, first, node_id, output, ctx);
+    }
+    Ok(node_id)
+}
+
+// Records the nodes added since `first` that `node_id` does not reach. Nodes already reported
+// for an inner expression are not reported again for the expressions containing it.
+fn report_orphans(
+    network: &Network,
+    first: usize,
+    node_id: usize,
+    expr: &Expr,
+    ctx: &mut BuildContext,
+) {
+    let nodes: Vec<usize> = network
+        .unreachable_from(node_id, first)
+        .into_iter()
+        .filter(|n| !ctx.orphans.iter().any(|(_, reported)| reported.contains(n)))
+        .collect();
+    if !nodes.is_empty() {
+        ctx.orphans.push((expr.to_string(), nodes));
+    }
+}
+
+// A quantile argument, which must be a constant Float in [0, 1].
+fn constant_quantile(arg: Option<&Expr>, output: &Expr) -> Result<f64, ParserError> {
+    let q = match arg {
+        Some(Expr::Literal(crate::expr::Literal::Float(q))) => Some(*q),
+        // An Int would reach the operator as Signal::Int, which it cannot take.
+        Some(Expr::Literal(crate::expr::Literal::Integer(q))) => {
+            return Err(ParserError::SemanticError(format!(
+                "Quantile must be a Float, write {}.0: {}",
+                q, output
+            )));
+        }
+        _ => None,
+    };
+    match q {
+        Some(q) if (0.0..=1.0).contains(&q) => Ok(q),
+        Some(q) => Err(ParserError::SemanticError(format!(
+            "Quantile {} is outside [0, 1]: {}",
+            q, output
+        ))),
+        None => Err(ParserError::SemanticError(format!(
+            "Quantile must be a constant: {}",
+            output
+        ))),
+    }
+}
+
+fn build_node(
+    network: &mut Network,
+    output: &Expr,
+    assignments: &HashMap<&str, &Expr>,
+    ctx: &mut BuildContext,
+    is_value: bool,
 ) -> Result<usize, ParserError> {
     match output {
         Expr::Literal(l) => Ok(network.add_node(NodeType::Literal(l.clone()))),
         Expr::Identifier(id) => {
             if let Some(expr) = assignments.get(id.as_str()) {
-                build_ast(network, expr, assignments, behaviors, behaviors_ptr)
+                let node_id = build_ast(network, expr, assignments, ctx, true);
+                if node_id.is_err() && ctx.failed_at.is_none() {
+                    ctx.failed_at = Some((ctx.expanding.last().cloned(), Some(id.clone())));
+                }
+                node_id
+            } else if ctx
+                .behaviors
+                .get(id.as_str())
+                .is_some_and(|b| b.is_nullary())
+            {
+                let call = Expr::Call {
+                    fn_name: id.clone(),
+                    args: vec![],
+                };
+                build_ast(network, &call, assignments, ctx, is_value)
+            } else if ctx.allow_undeclared_sources {
+                let name =
+                    network.add_node(NodeType::Literal(crate::expr::Literal::String(id.clone())));
+                let node_id = network.add_node(NodeType::Operator("data".into()));
+                network.add_child(node_id, name);
+                Ok(node_id)
             } else {
                 Err(ParserError::SemanticError(format!(
                     "Undefined identifier: {}",
@@ -106,28 +1124,179 @@ fn build_ast(
                 )))
             }
         }
+        Expr::Call { fn_name, args }
+            if fn_name == "apply" && !ctx.behaviors.contains_key("apply") =>
+        {
+            // apply(lambda, args...) inlines the lambda body with its parameters replaced.
+            let lambda = match args.first() {
+                Some(Expr::Identifier(id)) => assignments.get(id.as_str()).copied(),
+                first => first,
+            };
+            let Some(Expr::Lambda { params, body }) = lambda else {
+                return Err(ParserError::SemanticError(format!(
+                    "apply expects a lambda as its first argument: {}",
+                    output
+                )));
+            };
+            if params.len() != args.len() - 1 {
+                return Err(ParserError::SemanticError(format!(
+                    "Lambda takes {} arguments, got {}: {}",
+                    params.len(),
+                    args.len() - 1,
+                    output
+                )));
+            }
+            let bindings: HashMap<&str, &Expr> =
+                params.iter().map(|p| p.as_str()).zip(&args[1..]).collect();
+            build_ast(
+                network,
+                &substitute(body, &bindings),
+                assignments,
+                ctx,
+                is_value,
+            )
+        }
+        Expr::Call { fn_name, args }
+            if fn_name == "returns" && !ctx.behaviors.contains_key("returns") =>
+        {
+            // returns(x, n) is the percentage change over n periods, ts_return with a checked period.
+            match args.as_slice() {
+                [
+                    _,
+                    Expr::Literal(crate::expr::Literal::Integer(n) | crate::expr::Literal::Days(n)),
+                ] if *n > 0 => {}
+                _ => {
+                    return Err(ParserError::SemanticError(format!(
+                        "returns expects a series and a positive integer period: {}",
+                        output
+                    )));
+                }
+            }
+            let ts_return = Expr::Call {
+                fn_name: "ts_return".to_string(),
+                args: args.clone(),
+            };
+            build_ast(network, &ts_return, assignments, ctx, is_value)
+        }
         Expr::Call { fn_name, args } => {
+            if let Some(&behavior) = ctx.behaviors.get(fn_name.as_str())
+                && behavior.deprecated.is_some()
+                && !ctx.deprecated.contains(fn_name)
+            {
+                ctx.deprecated.push(fn_name.clone());
+            }
+            let impl_bodies = ctx.impl_bodies;
+            if let Some((flow_assignments, flow_output)) = impl_bodies.get(fn_name.as_str()) {
+                // Recursion between implementing flows was rejected by impl_order.
+                let flow_assignments: HashMap<&str, &Expr> = flow_assignments
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v))
+                    .collect();
+                ctx.expanding.push(fn_name.clone());
+                ctx.inlined.insert(fn_name.clone());
+                let node_id = build_ast(network, flow_output, &flow_assignments, ctx, is_value);
+                if node_id.is_err() && ctx.failed_at.is_none() {
+                    ctx.failed_at = Some((Some(fn_name.clone()), None));
+                }
+                ctx.expanding.pop();
+                return node_id;
+            }
+
             let mut arg_indices: Vec<usize> = Vec::new();
             for arg in args {
-                arg_indices.push(build_ast(
-                    network,
-                    arg,
-                    assignments,
-                    behaviors,
-                    behaviors_ptr,
-                )?);
-            }
-
-            if behaviors.contains_key(fn_name.as_str()) {
-                let node_id =
-                    network.add_node(NodeType::Behavior(behaviors[fn_name.as_str()].clone()));
+                arg_indices.push(build_ast(network, arg, assignments, ctx, true)?);
+            }
+
+            if let Some(&behavior) = ctx.behaviors.get(fn_name.as_str()) {
+                if is_value && behavior.output == Signal::Void {
+                    return Err(ParserError::SemanticError(format!(
+                        "Behavior {} does not return a value",
+                        fn_name
+                    )));
+                }
+                // Omitted trailing arguments take their defaults. Defaults cannot see flow variables.
+                for default in behavior.defaults.iter().skip(args.len()) {
+                    let Some(expr) = default else {
+                        return Err(ParserError::SemanticError(format!(
+                            "Behavior {} expects {} arguments, got {}",
+                            fn_name,
+                            behavior.inputs.len(),
+                            args.len()
+                        )));
+                    };
+                    arg_indices.push(build_ast(network, expr, &HashMap::new(), ctx, true)?);
+                }
+                let node_id = network.add_node(NodeType::Behavior(behavior.clone()));
                 for child_id in arg_indices {
                     network.add_child(node_id, child_id);
                 }
-                behaviors_ptr.push(node_id);
+                ctx.behaviors_ptr.push(node_id);
                 Ok(node_id)
             } else {
-                let spec: &OperatorSpec = fn_name.as_str().into();
+                if DIVISION_OPERATORS.contains(&fn_name.as_str())
+                    && let Some(Expr::Literal(lit)) = args.get(1)
+                    && is_zero(lit)
+                {
+                    return Err(ParserError::SemanticError(format!(
+                        "Division by zero: {}",
+                        output
+                    )));
+                }
+                if fn_name == "quantile" {
+                    constant_quantile(args.get(1), output)?;
+                }
+                if fn_name == "winsorize" {
+                    // winsorize(x, lower, upper) clips each row to its lower and upper quantile.
+                    let lower = constant_quantile(args.get(1), output)?;
+                    let upper = constant_quantile(args.get(2), output)?;
+                    if lower >= upper {
+                        return Err(ParserError::SemanticError(format!(
+                            "Lower quantile {} is not below the upper quantile {}: {}",
+                            lower, upper, output
+                        )));
+                    }
+                }
+                let Some(spec) = OperatorSpec::lookup(fn_name) else {
+                    // Keep building so every unknown operator is reported at once.
+                    // The placeholder never leaves analyze_program.
+                    if !ctx.unknown_ops.contains(fn_name) {
+                        ctx.unknown_ops.push(fn_name.clone());
+                    }
+                    return Ok(
+                        network.add_node(NodeType::Literal(crate::expr::Literal::String(
+                            fn_name.clone(),
+                        ))),
+                    );
+                };
+                // A day count is a window length, so it may only fill an Int parameter.
+                for (arg, expected) in args.iter().zip(spec.inputs.iter()) {
+                    if let Expr::Literal(crate::expr::Literal::Days(_)) = arg
+                        && !matches!(expected, Signal::Int(_))
+                    {
+                        return Err(ParserError::SemanticError(format!(
+                            "Day count {} used where {:?} is expected: {}",
+                            arg, expected, output
+                        )));
+                    }
+                }
+                // A rolling window (the Int parameter of ts_sum, ts_max, ...) holds at least one row.
+                if fn_name.starts_with("ts_") && fn_name != "ts_return" {
+                    for (arg, expected) in args.iter().zip(spec.inputs.iter()) {
+                        if let (
+                            Expr::Literal(
+                                crate::expr::Literal::Integer(n) | crate::expr::Literal::Days(n),
+                            ),
+                            Signal::Int(_),
+                        ) = (arg, expected)
+                            && *n <= 0
+                        {
+                            return Err(ParserError::SemanticError(format!(
+                                "Window {} of {} must be positive: {}",
+                                n, fn_name, output
+                            )));
+                        }
+                    }
+                }
                 let node_id = network.add_node(NodeType::Operator(spec));
                 for child_id in arg_indices {
                     network.add_child(node_id, child_id);
@@ -135,6 +1304,10 @@ fn build_ast(
                 Ok(node_id)
             }
         }
+        Expr::Lambda { .. } => Err(ParserError::SemanticError(format!(
+            "Lambda can only be used as the first argument of apply: {}",
+            output
+        ))),
         Expr::List(_exprs) => panic!("Unexpected list expression"),
         Expr::Range {
             start: _,
@@ -144,6 +1317,12 @@ fn build_ast(
     }
 }
 
+pub(crate) fn parse_source(input: &str) -> Result<InputCode, ParserError> {
+    let mut pairs = CometParser::parse(Rule::program, input)?;
+    let program_pair = pairs.next().ok_or(ParserError::MissingToken)?;
+    parse_program(program_pair)
+}
+
 fn parse_program(pair: pest::iterators::Pair<Rule>) -> Result<InputCode, ParserError> {
     // receives tokens, outputs Behavior and Flows
     let mut declarations = Vec::new();
@@ -163,10 +1342,27 @@ fn parse_declaration(
     let inner = pair.into_inner().next().unwrap();
     match inner.as_rule() {
         Rule::import_decl => {
-            let s = inner.into_inner().nth(1).unwrap().as_str();
-            Ok(crate::behavior::InputDecl::Import(
-                s.trim_matches('"').to_string(),
-            ))
+            let mut reexport = false;
+            let mut path = None;
+            let mut feature = None;
+            let mut symbols = None;
+            for p in inner.into_inner() {
+                match p.as_rule() {
+                    Rule::k_export => reexport = true,
+                    Rule::string_literal => path = Some(p.as_str().trim_matches('"').to_string()),
+                    Rule::import_symbols => {
+                        symbols = Some(p.into_inner().map(|s| s.as_str().to_string()).collect())
+                    }
+                    Rule::identifier => feature = Some(p.as_str().to_string()),
+                    _ => {}
+                }
+            }
+            Ok(crate::behavior::InputDecl::Import(ImportDecl {
+                path: path.ok_or(ParserError::MissingToken)?,
+                reexport,
+                feature,
+                symbols,
+            }))
         }
         Rule::behavior_decl => parse_behavior(inner),
         Rule::flow_decl => parse_flow(inner),
@@ -178,10 +1374,39 @@ fn parse_behavior(
     pair: pest::iterators::Pair<Rule>,
 ) -> Result<crate::behavior::InputDecl, ParserError> {
     let mut inner = pair.into_inner();
-    inner.next(); // skip k_behavior
-    let name = inner.next().unwrap().as_str().to_string();
+    let mut deprecated = None;
+    let mut doc: Vec<&str> = Vec::new();
+    for p in inner.by_ref() {
+        if p.as_rule() == Rule::doc_comment {
+            let line = p.as_str().trim_start_matches("///");
+            doc.push(line.strip_prefix(' ').unwrap_or(line).trim_end());
+            continue;
+        }
+        if p.as_rule() != Rule::annotation {
+            break; // k_behavior
+        }
+        let mut annotation = p.into_inner();
+        let key = annotation.next().unwrap().as_str();
+        let value = annotation
+            .next()
+            .unwrap()
+            .as_str()
+            .trim_matches('"')
+            .to_string();
+        match key {
+            "deprecated" => deprecated = Some(value),
+            _ => {
+                return Err(ParserError::SemanticError(format!(
+                    "Unknown annotation: @{}",
+                    key
+                )));
+            }
+        }
+    }
+    let name = inner.next().unwrap().as_str().to_string();
 
     let mut inputs = Vec::new();
+    let mut defaults = Vec::new();
     let mut props_pair = None;
     let mut types_pair = None;
 
@@ -190,9 +1415,24 @@ fn parse_behavior(
             Rule::typed_arg_list => {
                 for typed_arg in p.into_inner() {
                     let mut arg_inner = typed_arg.into_inner();
-                    let _arg_name = arg_inner.next().unwrap().as_str().to_string();
-                    let arg_type = parse_types(arg_inner.next().unwrap())?;
+                    let arg_name = arg_inner.next().unwrap().as_str().to_string();
+                    let type_pair = arg_inner.next().unwrap();
+                    let ty = type_pair.as_str().to_string();
+                    let arg_type =
+                        parse_types(type_pair).map_err(|_| ParserError::UnknownParamType {
+                            func: name.clone(),
+                            param: arg_name.clone(),
+                            ty,
+                        })?;
+                    let default = arg_inner.next().map(parse_expr).transpose()?;
+                    if default.is_none() && defaults.iter().any(Option::is_some) {
+                        return Err(ParserError::SemanticError(format!(
+                            "Argument {} of behavior {} follows a defaulted argument",
+                            arg_name, name
+                        )));
+                    }
                     inputs.push(arg_type);
+                    defaults.push(default);
                 }
             }
             Rule::behavior_props_block => {
@@ -205,8 +1445,14 @@ fn parse_behavior(
         }
     }
 
-    let output_type = parse_types(types_pair.unwrap())?;
+    let output_type = match types_pair {
+        Some(p) => parse_types(p)?,
+        None => Signal::Void,
+    };
     let mut bdecl = crate::behavior::BehaviorDecl::new(&name, inputs, output_type);
+    bdecl.defaults = defaults;
+    bdecl.deprecated = deprecated;
+    bdecl.doc = (!doc.is_empty()).then(|| doc.join("\n"));
 
     if let Some(block) = props_pair {
         if let Some(props) = block.into_inner().next() {
@@ -221,6 +1467,8 @@ fn parse_behavior(
                     "supervised_epochs" => {
                         bdecl.supervised_epochs = Some(extract_int(&prop_val)? as usize)
                     }
+                    "priority" => bdecl.priority = Some(extract_int(&prop_val)?),
+                    "category" => bdecl.category = Some(extract_string(&prop_val)?),
                     "operators" => bdecl.operators = Some(extract_ident_list(&prop_val)?),
                     "integers" => bdecl.integers = Some(extract_int_list(&prop_val)?),
                     "floats" => bdecl.floats = Some(extract_float_list(&prop_val)?),
@@ -236,19 +1484,13 @@ fn parse_behavior(
         }
     }
 
+    bdecl.dedup_lists();
     Ok(crate::behavior::InputDecl::Behavior(bdecl))
 }
 
 fn parse_types(pair: pest::iterators::Pair<Rule>) -> Result<Signal, ParserError> {
-    match pair.as_str() {
-        "Void" => Ok(Signal::Void),
-        "Float" => Ok(Signal::Float(None)),
-        "Int" => Ok(Signal::Int(None)),
-        "String" => Ok(Signal::String(None)),
-        "Vector" => Ok(Signal::DataFrame(None)),
-        "DataFrame" => Ok(Signal::DataFrame(None)),
-        s => Err(ParserError::SemanticError(format!("Unknown type: {}", s))),
-    }
+    signal_type(pair.as_str())
+        .ok_or_else(|| ParserError::SemanticError(format!("Unknown type: {}", pair.as_str())))
 }
 
 fn extract_string(pair: &pest::iterators::Pair<Rule>) -> Result<String, ParserError> {
@@ -368,18 +1610,40 @@ fn extract_string_list(pair: &pest::iterators::Pair<Rule>) -> Result<Vec<String>
 fn parse_flow(
     pair: pest::iterators::Pair<Rule>,
 ) -> Result<crate::behavior::InputDecl, ParserError> {
-    let mut inner = pair.into_inner();
+    let mut inner = pair.into_inner().peekable();
+    let export = inner.peek().map(|p| p.as_rule()) == Some(Rule::k_export);
+    if export {
+        inner.next(); // k_export
+    }
     inner.next(); // k_flow
     let name = inner.next().unwrap().as_str().to_string();
+    let mut implements = None;
+    if inner.peek().map(|p| p.as_rule()) == Some(Rule::k_implements) {
+        inner.next(); // k_implements
+        implements = Some(inner.next().unwrap().as_str().to_string());
+    }
 
     let mut body = Vec::new();
+    let mut spans = Vec::new();
     for p in inner {
+        match p.as_rule() {
+            Rule::assignment_stmt | Rule::expr => spans.push(Span {
+                start: p.as_span().start(),
+                end: content_end(&p),
+            }),
+            _ => {}
+        }
         match p.as_rule() {
             Rule::assignment_stmt => {
                 let mut assn_inner = p.into_inner();
                 let target = assn_inner.next().unwrap().as_str().to_string();
+                let ty = if assn_inner.peek().map(|p| p.as_rule()) == Some(Rule::identifier) {
+                    Some(parse_types(assn_inner.next().unwrap())?)
+                } else {
+                    None
+                };
                 let expr = parse_expr(assn_inner.next().unwrap())?;
-                body.push(crate::expr::FlowStmt::Assignment { target, expr });
+                body.push(crate::expr::FlowStmt::Assignment { target, ty, expr });
             }
             Rule::expr => {
                 body.push(crate::expr::FlowStmt::Expr(parse_expr(p)?));
@@ -389,11 +1653,80 @@ fn parse_flow(
     }
 
     Ok(crate::behavior::InputDecl::Flow(
-        crate::behavior::FlowDecl { name, body },
+        crate::behavior::FlowDecl {
+            name,
+            body,
+            export,
+            implements,
+            spans,
+        },
     ))
 }
 
+// Where a statement ends. pest keeps the whitespace and comments skipped while looking for an
+// optional tail, e.g. the ^ after an atom, in the span of the rule.
+fn content_end(pair: &pest::iterators::Pair<Rule>) -> usize {
+    let parenthesized = pair.as_rule() == Rule::atom && pair.as_str().starts_with('(');
+    match pair.as_rule() {
+        Rule::assignment_stmt | Rule::expr | Rule::lambda | Rule::atom if !parenthesized => pair
+            .clone()
+            .into_inner()
+            .map(|p| content_end(&p))
+            .max()
+            .unwrap_or(pair.as_span().start()),
+        _ => pair.as_span().end(),
+    }
+}
+
 fn parse_expr(pair: pest::iterators::Pair<Rule>) -> Result<crate::expr::Expr, ParserError> {
+    let mut inner = pair.into_inner();
+    let first = inner.next().unwrap();
+    if first.as_rule() == Rule::lambda {
+        let mut lambda = first.into_inner();
+        let params = lambda
+            .next()
+            .unwrap()
+            .into_inner()
+            .map(|p| p.as_str().to_string())
+            .collect();
+        let body = parse_expr(lambda.next().unwrap())?;
+        return Ok(Expr::Lambda {
+            params,
+            body: Box::new(body),
+        });
+    }
+    let base = parse_atom(first)?;
+    match inner.next() {
+        Some(exponent) => {
+            // pow takes a Float exponent, so integer literals are widened.
+            let exponent = match parse_expr(exponent)? {
+                Expr::Literal(crate::expr::Literal::Integer(i)) => {
+                    Expr::Literal(crate::expr::Literal::Float(i as f64))
+                }
+                e => e,
+            };
+            // pow needs a DataFrame base, so a constant power such as the 2 ^ 3 in x ^ 2 ^ 3
+            // is folded.
+            let constant = match &base {
+                Expr::Literal(crate::expr::Literal::Integer(b)) => Some(*b as f64),
+                Expr::Literal(crate::expr::Literal::Float(b)) => Some(*b),
+                _ => None,
+            };
+            match (constant, &exponent) {
+                (Some(b), Expr::Literal(crate::expr::Literal::Float(e))) => {
+                    Ok(Expr::Literal(crate::expr::Literal::Float(b.powf(*e))))
+                }
+                _ => Ok(Expr::Call {
+                    fn_name: "pow".to_string(),
+                    args: vec![base, exponent],
+                }),
+            }
+        }
+        None => Ok(base),
+    }
+}
+
+fn parse_atom(pair: pest::iterators::Pair<Rule>) -> Result<crate::expr::Expr, ParserError> {
     let inner = pair.clone().into_inner().next().unwrap();
     // Case 1: The expression is naturally wrapping another expression (e.g., grouped by parentheses or nested)
     if inner.as_rule() == Rule::expr {
@@ -490,7 +1823,12 @@ fn parse_literal(pair: pest::iterators::Pair<Rule>) -> Result<crate::expr::Liter
         Rule::int_literal => Ok(crate::expr::Literal::Integer(
             inner.as_str().parse().unwrap(),
         )),
-        Rule::float_literal => Ok(crate::expr::Literal::Float(inner.as_str().parse().unwrap())),
+        Rule::days_literal => Ok(crate::expr::Literal::Days(
+            inner.as_str().trim_end_matches('d').parse().unwrap(),
+        )),
+        Rule::float_literal => Ok(crate::expr::Literal::Float(
+            inner.as_str().trim_end_matches('f').parse().unwrap(),
+        )),
         Rule::string_literal => Ok(crate::expr::Literal::String(
             inner.as_str().trim_matches('"').to_string(),
         )),
@@ -524,3 +1862,1739 @@ fn test_parse_behavior_decl() {
     println!("{:?}", network.format_node(network.root));
     println!("{:?}", undetermined_nodes);
 }
+
+#[test]
+fn test_analyze_program_in_memory() {
+    use crate::expr::Literal;
+
+    // Comparator(data("volume"), data("adv20")), built without any source text or file.
+    let behavior = BehaviorDecl::new(
+        "Comparator",
+        vec![Signal::DataFrame(None), Signal::DataFrame(None)],
+        Signal::DataFrame(None),
+    );
+    let data_call = |name: &str| Expr::Call {
+        fn_name: "data".to_string(),
+        args: vec![Expr::Literal(Literal::String(name.to_string()))],
+    };
+    let flow = FlowDecl {
+        name: "volume_spike".to_string(),
+        body: vec![
+            FlowStmt::Assignment {
+                target: "volume".to_string(),
+                ty: None,
+                expr: data_call("volume"),
+            },
+            FlowStmt::Expr(Expr::Call {
+                fn_name: "Comparator".to_string(),
+                args: vec![Expr::Identifier("volume".to_string()), data_call("adv20")],
+            }),
+        ],
+        export: false,
+        implements: None,
+        spans: vec![],
+    };
+    let code = vec![InputDecl::Behavior(behavior), InputDecl::Flow(flow.clone())];
+    let (network, behavior_nodes) = analyze_program(code).unwrap();
+    assert_eq!(
+        network.format_node(network.root),
+        "Comparator(data(\"volume\"), data(\"adv20\"))"
+    );
+    assert_eq!(behavior_nodes, vec![network.root]);
+
+    // Imports need a file system, so in-memory analysis rejects them.
+    let import = ImportDecl {
+        path: "lib.cm".to_string(),
+        reexport: false,
+        feature: None,
+        symbols: None,
+    };
+    let code = vec![InputDecl::Import(import), InputDecl::Flow(flow)];
+    assert!(matches!(
+        analyze_program(code),
+        Err(ParserError::SemanticError(_))
+    ));
+}
+
+#[test]
+fn test_parse_behavior_without_return_type() {
+    let behavior = r#"
+        Behavior Logger(signal: DataFrame) {
+            operators = [add], integers = [], floats = [], strings = []
+        }
+    "#;
+
+    // Used as the flow's output, a Void behavior is allowed.
+    let input = format!(
+        "{}\nFlow log_volume {{\n volume = data(\"volume\")\n Logger(volume)\n }}",
+        behavior
+    );
+    let (network, behavior_nodes) = parse(&input).unwrap();
+    let (_, decl) = network.get_behavior();
+    assert_eq!(decl.output, Signal::Void);
+    assert_eq!(behavior_nodes, vec![network.root]);
+
+    // Assigned and then used as a value, it is rejected.
+    let input = format!(
+        "{}\nFlow log_volume {{\n logged = Logger(data(\"volume\"))\n divide(logged, data(\"adv20\"))\n }}",
+        behavior
+    );
+    match parse(&input) {
+        Err(ParserError::SemanticError(msg)) => assert!(msg.contains("Logger")),
+        other => panic!("Expected semantic error, got {:?}", other.map(|(n, _)| n)),
+    }
+}
+
+#[test]
+fn test_parse_behavior_dedups_search_space() {
+    let input = r#"
+        Behavior Comparator(signal: DataFrame, reference: DataFrame) {
+            operators = [add, divide, add], integers = [5, 21, 5],
+            floats = [0.5, 0.5, 1.0], strings = ["volume", "volume"]
+        } -> DataFrame
+
+        Flow volume_spike {
+            Comparator(data("volume"), data("adv20"))
+        }
+    "#;
+    let (network, _) = parse(input).unwrap();
+    let (_, decl) = network.get_behavior();
+    assert_eq!(
+        decl.operators,
+        Some(vec!["add".to_string(), "divide".to_string()])
+    );
+    assert_eq!(decl.integers, Some(vec![5, 21]));
+    assert_eq!(decl.floats, Some(vec![0.5, 1.0]));
+    assert_eq!(decl.strings, Some(vec!["volume".to_string()]));
+}
+
+#[test]
+fn test_parse_export_flow() {
+    let input = r#"
+        Flow helper {
+            ts_mean(data("volume"), 5)
+        }
+
+        Export Flow volume_spike {
+            volume = data("volume")
+            divide(volume, ts_mean(volume, 20))
+        }
+
+        Flow other_helper {
+            data("adv20")
+        }
+    "#;
+    let mut pairs = CometParser::parse(Rule::program, input).unwrap();
+    let code = parse_program(pairs.next().unwrap()).unwrap();
+    let exports: Vec<(String, bool)> = code
+        .iter()
+        .filter_map(|decl| match decl {
+            InputDecl::Flow(f) => Some((f.name.clone(), f.export)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        exports,
+        vec![
+            ("helper".to_string(), false),
+            ("volume_spike".to_string(), true),
+            ("other_helper".to_string(), false)
+        ]
+    );
+
+    // Only the exported flow is built.
+    let (network, _) = parse(input).unwrap();
+    assert_eq!(
+        network.format_node(network.root),
+        "divide(data(\"volume\"), ts_mean(data(\"volume\"), 20))"
+    );
+}
+
+#[test]
+fn test_parse_behavior_priority() {
+    let input = r#"
+        Behavior Ratio(signal: DataFrame, reference: DataFrame) {
+            operators = [divide], integers = [], floats = [], strings = []
+        } -> DataFrame
+
+        Behavior Spread(signal: DataFrame, reference: DataFrame) {
+            operators = [subtract], integers = [], floats = [], strings = [], priority = 10
+        } -> DataFrame
+
+        Flow volume_spike {
+            volume = data("volume")
+            adv20 = data("adv20")
+            add(Ratio(volume, adv20), Spread(volume, adv20))
+        }
+    "#;
+    let (network, behavior_nodes) = parse(input).unwrap();
+    let names: Vec<(String, Option<i64>)> = behavior_nodes
+        .iter()
+        .map(|&idx| match &network.nodes[idx].node_type {
+            NodeType::Behavior(b) => (b.name.clone().unwrap(), b.priority),
+            _ => unreachable!(),
+        })
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            ("Spread".to_string(), Some(10)),
+            ("Ratio".to_string(), None)
+        ]
+    );
+}
+
+#[test]
+fn test_parse_file_conflicting_import() {
+    let tmp = crate::testing::TempDir::new("conflicting_import");
+    let dir = tmp.path();
+    let behavior = r#"
+        Behavior Comparator(signal: DataFrame, reference: DataFrame) {
+            operators = [divide], integers = [], floats = [], strings = []
+        } -> DataFrame
+    "#;
+    std::fs::write(dir.join("a.cm"), behavior).unwrap();
+    std::fs::write(dir.join("b.cm"), behavior).unwrap();
+    let flow = r#"
+        Flow volume_spike {
+            Comparator(data("volume"), data("adv20"))
+        }
+    "#;
+
+    // Importing the same file twice is not a conflict.
+    std::fs::write(
+        dir.join("diamond.cm"),
+        format!("Import \"a.cm\"\nImport \"./a.cm\"\n{}", flow),
+    )
+    .unwrap();
+    let (network, _) = parse_file(&dir.join("diamond.cm")).unwrap();
+    assert_eq!(
+        network.format_node(network.root),
+        "Comparator(data(\"volume\"), data(\"adv20\"))"
+    );
+
+    // Neither is a second file with an identical definition.
+    std::fs::write(
+        dir.join("reexport.cm"),
+        format!("Import \"a.cm\"\nImport \"b.cm\"\n{}", flow),
+    )
+    .unwrap();
+    let (network, _) = parse_file(&dir.join("reexport.cm")).unwrap();
+    assert_eq!(network.get_behavior_indices().len(), 1);
+
+    // A different signature under the same name is.
+    std::fs::write(
+        dir.join("c.cm"),
+        behavior.replace("reference: DataFrame", "window: Int"),
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("incompatible.cm"),
+        format!("Import \"a.cm\"\nImport \"c.cm\"\n{}", flow),
+    )
+    .unwrap();
+    match parse_file(&dir.join("incompatible.cm")) {
+        Err(ParserError::IncompatibleRedefinition { name, sig_a, sig_b }) => {
+            assert_eq!(name, "Comparator");
+            assert_eq!(sig_a, "Comparator(DataFrame, DataFrame) -> DataFrame");
+            assert_eq!(sig_b, "Comparator(DataFrame, Int) -> DataFrame");
+        }
+        other => panic!(
+            "Expected incompatible redefinition, got {:?}",
+            other.map(|(n, _)| n)
+        ),
+    }
+
+    // So are two definitions that only differ in how they are searched.
+    std::fs::write(
+        dir.join("d.cm"),
+        behavior.replace("[divide]", "[divide, subtract]"),
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("main.cm"),
+        format!("Import \"a.cm\"\nImport \"d.cm\"\n{}", flow),
+    )
+    .unwrap();
+    match parse_file(&dir.join("main.cm")) {
+        Err(ParserError::ConflictingImport {
+            symbol,
+            path_a,
+            path_b,
+        }) => {
+            assert_eq!(symbol, "Comparator");
+            assert!(path_a.ends_with("a.cm"));
+            assert!(path_b.ends_with("d.cm"));
+        }
+        other => panic!(
+            "Expected conflicting import, got {:?}",
+            other.map(|(n, _)| n)
+        ),
+    }
+}
+
+#[test]
+fn test_division_by_literal_zero() {
+    let flow = |divisor: &str| {
+        format!(
+            "Flow scaled {{\n volume = data(\"volume\")\n divide(volume, {})\n }}",
+            divisor
+        )
+    };
+    assert!(parse(&flow("2")).is_ok());
+    assert!(parse(&flow("0.5")).is_ok());
+    for zero in ["0", "0.0"] {
+        match parse(&flow(zero)) {
+            Err(ParserError::SemanticError(msg)) => assert!(msg.contains("Division by zero")),
+            other => panic!("Expected division by zero, got {:?}", other.map(|(n, _)| n)),
+        }
+    }
+}
+
+#[test]
+fn test_parse_bare_expression_statements() {
+    let input = r#"
+        Flow volume_spike {
+            volume = data("volume")
+            ts_mean(volume, 5)
+            adv20 = data("adv20")
+            divide(volume, adv20)
+        }
+    "#;
+    let mut pairs = CometParser::parse(Rule::program, input).unwrap();
+    let code = parse_program(pairs.next().unwrap()).unwrap();
+    let body = match &code[0] {
+        InputDecl::Flow(f) => f.body.clone(),
+        _ => panic!("Expected flow"),
+    };
+    assert_eq!(body.len(), 4);
+    assert!(matches!(body[1], FlowStmt::Expr(_)));
+
+    let (network, _) = parse(input).unwrap();
+    assert_eq!(
+        network.format_node(network.root),
+        "divide(data(\"volume\"), data(\"adv20\"))"
+    );
+
+    // A flow ending in an assignment has no output.
+    let input = "Flow empty { data(\"adv20\") volume = data(\"volume\") }";
+    assert!(matches!(parse(input), Err(ParserError::SemanticError(_))));
+}
+
+#[test]
+fn test_parse_behavior_category() {
+    let input = r#"
+        Behavior Zscore(signal: DataFrame) {
+            operators = [cs_zscore], integers = [], floats = [], strings = [], category = "normalizer"
+        } -> DataFrame
+
+        Behavior Rank(signal: DataFrame) {
+            operators = [rank], integers = [], floats = [], strings = [], category = "normalizer"
+        } -> DataFrame
+
+        Behavior Comparator(signal: DataFrame, reference: DataFrame) {
+            operators = [divide], integers = [], floats = [], strings = []
+        } -> DataFrame
+    "#;
+    let mut pairs = CometParser::parse(Rule::program, input).unwrap();
+    let code = parse_program(pairs.next().unwrap()).unwrap();
+
+    let names: Vec<String> = behaviors_in_category(&code, "normalizer")
+        .iter()
+        .map(|b| b.name.clone().unwrap())
+        .collect();
+    assert_eq!(names, vec!["Zscore".to_string(), "Rank".to_string()]);
+    assert!(behaviors_in_category(&code, "comparator").is_empty());
+}
+
+#[test]
+fn test_parse_flow_implements_behavior() {
+    let input = r#"
+        Behavior Generator() {
+            operators = [add], integers = [], floats = [], strings = []
+        } -> DataFrame
+
+        Flow volume_ratio Implements Generator {
+            volume = data("volume")
+            divide(volume, data("adv20"))
+        }
+
+        Flow strategy {
+            rank(Generator())
+        }
+    "#;
+    let (network, behavior_nodes) = parse(input).unwrap();
+    assert_eq!(
+        network.format_node(network.root),
+        "rank(divide(data(\"volume\"), data(\"adv20\")))"
+    );
+    assert!(behavior_nodes.is_empty());
+}
+
+#[test]
+fn test_parse_pow_operator() {
+    let output_of = |expr: &str| {
+        // Assigned, so that a leading ( is not read as a call on the previous line.
+        let input = format!("Flow f {{\n x = data(\"close\")\n y = {}\n y\n }}", expr);
+        let mut pairs = CometParser::parse(Rule::program, &input).unwrap();
+        let code = parse_program(pairs.next().unwrap()).unwrap();
+        match &code[0] {
+            InputDecl::Flow(f) => f.body[1].to_string().replacen("y = ", "", 1),
+            _ => panic!("Expected flow"),
+        }
+    };
+    assert_eq!(output_of("x ^ 2"), "pow(x, 2.0)");
+    assert_eq!(output_of("x ** 0.5"), "pow(x, 0.5)");
+    // Right associative
+    assert_eq!(output_of("x ^ 2 ^ 3"), "pow(x, 8.0)");
+    assert_eq!(output_of("x ^ 0.5 ^ 2"), "pow(x, 0.25)");
+    assert_eq!(output_of("(x ^ 2) ^ 3"), "pow(pow(x, 2.0), 3.0)");
+    // Binds tighter than a call argument separator
+    assert_eq!(output_of("divide(x ^ 2, x)"), "divide(pow(x, 2.0), x)");
+
+    let (network, _) = parse("Flow f { data(\"close\") ^ 2 }").unwrap();
+    assert_eq!(
+        network.format_node(network.root),
+        "pow(data(\"close\"), 2.0)"
+    );
+}
+
+#[test]
+fn test_analyze_program_with_seeds() {
+    use crate::expr::Literal;
+
+    let input = r#"
+        Flow volume_spike {
+            divide(prices, ts_mean(prices, 20))
+        }
+    "#;
+    let mut pairs = CometParser::parse(Rule::program, input).unwrap();
+    let code = parse_program(pairs.next().unwrap()).unwrap();
+    assert!(analyze_program(code.clone()).is_err());
+
+    let seeds = HashMap::from([(
+        "prices".to_string(),
+        Expr::Call {
+            fn_name: "data".to_string(),
+            args: vec![Expr::Literal(Literal::String("close".to_string()))],
+        },
+    )]);
+    let (network, _) = analyze_program_with(code, &seeds).unwrap();
+    assert_eq!(
+        network.format_node(network.root),
+        "divide(data(\"close\"), ts_mean(data(\"close\"), 20))"
+    );
+}
+
+#[test]
+fn test_parse_file_import_errors() {
+    let tmp = crate::testing::TempDir::new("import_errors");
+    let dir = tmp.path();
+    let flow = r#"
+        Flow volume_spike {
+            data("volume")
+        }
+    "#;
+
+    std::fs::write(
+        dir.join("missing.cm"),
+        format!("Import \"nope.cm\"\n{}", flow),
+    )
+    .unwrap();
+    match parse_file(&dir.join("missing.cm")) {
+        Err(ParserError::ImportNotFound {
+            requested,
+            searched,
+        }) => {
+            assert_eq!(requested, "nope.cm");
+            assert_eq!(searched, vec![dir.canonicalize().unwrap()]);
+        }
+        other => panic!("Expected import not found, got {:?}", other.map(|(n, _)| n)),
+    }
+
+    // A missing root file is not a missing import.
+    match parse_file(&dir.join("absent.cm")) {
+        Err(ParserError::FileNotFound { path }) => assert_eq!(path, dir.join("absent.cm")),
+        other => panic!("Expected file not found, got {:?}", other.map(|(n, _)| n)),
+    }
+
+    std::fs::write(dir.join("broken.cm"), "Behavior Broken(").unwrap();
+    std::fs::write(
+        dir.join("main.cm"),
+        format!("Import \"broken.cm\"\n{}", flow),
+    )
+    .unwrap();
+    match parse_file(&dir.join("main.cm")) {
+        Err(ParserError::ImportParseFailed { path, source }) => {
+            assert!(path.ends_with("broken.cm"));
+            assert!(matches!(*source, ParserError::PestError(_)));
+        }
+        other => panic!(
+            "Expected import parse failure, got {:?}",
+            other.map(|(n, _)| n)
+        ),
+    }
+}
+
+#[test]
+fn test_parse_recursive_impl() {
+    let input = r#"
+        Behavior Generator() {
+            operators = [add], integers = [], floats = [], strings = []
+        } -> DataFrame
+
+        Behavior Smoother() {
+            operators = [ts_mean], integers = [5], floats = [], strings = []
+        } -> DataFrame
+
+        Flow self_ref Implements Generator {
+            rank(Generator())
+        }
+
+        Flow smooth Implements Smoother {
+            ts_mean(data("close"), 5)
+        }
+
+        Flow strategy {
+            add(Smoother(), Generator())
+        }
+    "#;
+    match parse(input) {
+        Err(ParserError::RecursiveImpl { chain }) => {
+            assert_eq!(
+                chain,
+                vec!["Generator".to_string(), "Generator".to_string()]
+            );
+        }
+        other => panic!("Expected recursive impl, got {:?}", other.map(|(n, _)| n)),
+    }
+}
+
+#[test]
+fn test_parse_literal_suffixes() {
+    use crate::expr::Literal;
+
+    let input = r#"
+        Flow f {
+            x = data("close")
+            divide(ts_mean(x, 21d), 0.5f)
+        }
+    "#;
+    let mut pairs = CometParser::parse(Rule::program, input).unwrap();
+    let code = parse_program(pairs.next().unwrap()).unwrap();
+    match &code[0] {
+        InputDecl::Flow(f) => assert_eq!(
+            f.body.last().unwrap(),
+            &FlowStmt::Expr(Expr::Call {
+                fn_name: "divide".to_string(),
+                args: vec![
+                    Expr::Call {
+                        fn_name: "ts_mean".to_string(),
+                        args: vec![
+                            Expr::Identifier("x".to_string()),
+                            Expr::Literal(Literal::Days(21)),
+                        ],
+                    },
+                    Expr::Literal(Literal::Float(0.5)),
+                ],
+            })
+        ),
+        _ => panic!("Expected flow"),
+    }
+
+    // A day count fills a window parameter, a bare integer still does too.
+    let (network, _) = parse(input).unwrap();
+    assert_eq!(
+        network.format_node(network.root),
+        "divide(ts_mean(data(\"close\"), 21d), 0.5)"
+    );
+    assert!(parse("Flow f { ts_mean(data(\"close\"), 21) }").is_ok());
+
+    // A day count is not a scalar.
+    assert!(matches!(
+        parse("Flow f { pow(data(\"close\"), 2d) }"),
+        Err(ParserError::SemanticError(_))
+    ));
+}
+
+#[test]
+fn test_explain() {
+    let input = r#"
+        Behavior Generator() {
+            operators = [add], integers = [], floats = [], strings = []
+        } -> DataFrame
+
+        Behavior Mix(a: DataFrame, b: DataFrame) {
+            operators = [add, divide], integers = [5], floats = [], strings = []
+        } -> DataFrame
+
+        Flow volume_ratio Implements Generator {
+            divide(data("volume"), data("adv20"))
+        }
+
+        Flow draft {
+            data("close")
+        }
+
+        Export Flow strategy {
+            x = Generator()
+            Mix(x, data("close"))
+        }
+    "#;
+    let mut pairs = CometParser::parse(Rule::program, input).unwrap();
+    let code = parse_program(pairs.next().unwrap()).unwrap();
+    let report = explain(code).unwrap();
+    assert!(report.contains("Output flow: strategy (exported)"));
+    assert!(report.contains("Skipped flow draft: strategy is exported"));
+    assert!(report.contains("Skipped flow volume_ratio: implements Generator"));
+    assert!(report.contains("Behavior Mix at node"));
+    assert!(report.contains("2 operators, 1 integers, 0 floats, 0 strings, 2 inputs"));
+}
+
+#[test]
+fn test_unknown_operators() {
+    let input = r#"
+        Behavior Mix(a: DataFrame) {
+            operators = [add, ts_magic], integers = [], floats = [], strings = []
+        } -> DataFrame
+
+        Flow f {
+            x = winsor(data("close"))
+            divide(Mix(x), smooth(x, 5))
+        }
+    "#;
+    // Only calls are checked. The operators a behavior lists are resolved when it is searched.
+    match parse(input) {
+        Err(ParserError::UnknownOperators(ops)) => {
+            assert_eq!(ops, vec!["winsor".to_string(), "smooth".to_string()])
+        }
+        other => panic!(
+            "Expected unknown operators, got {:?}",
+            other.map(|(n, _)| n)
+        ),
+    }
+}
+
+#[test]
+fn test_use_before_definition() {
+    let use_of = |input: &str| match parse(input) {
+        Err(ParserError::UseBeforeDefinition { var, stmt_index }) => (var, stmt_index),
+        other => panic!(
+            "Expected use before definition, got {:?}",
+            other.map(|(n, _)| n)
+        ),
+    };
+    assert_eq!(
+        use_of("Flow f { x = ts_mean(x, 5)\n x }"),
+        ("x".to_string(), 0)
+    );
+    assert_eq!(
+        use_of("Flow f { y = flip(x)\n x = data(\"close\")\n y }"),
+        ("x".to_string(), 0)
+    );
+    assert_eq!(
+        use_of("Flow f { x = data(\"close\")\n divide(x, z) }"),
+        ("z".to_string(), 1)
+    );
+
+    // Seeds count as defined before the first statement.
+    let mut pairs = CometParser::parse(Rule::program, "Flow f { flip(prices) }").unwrap();
+    let code = parse_program(pairs.next().unwrap()).unwrap();
+    let seeds = HashMap::from([(
+        "prices".to_string(),
+        Expr::Call {
+            fn_name: "data".to_string(),
+            args: vec![Expr::Literal(crate::expr::Literal::String(
+                "close".to_string(),
+            ))],
+        },
+    )]);
+    assert!(analyze_program_with(code, &seeds).is_ok());
+}
+
+#[test]
+fn test_allow_undeclared_sources() {
+    use crate::ast::Input;
+
+    let input = r#"
+        Flow f {
+            x = flip(vwap)
+            divide(x, data("close"))
+        }
+    "#;
+    let mut pairs = CometParser::parse(Rule::program, input).unwrap();
+    let code = parse_program(pairs.next().unwrap()).unwrap();
+    assert!(matches!(
+        analyze_program(code.clone()),
+        Err(ParserError::UseBeforeDefinition { .. })
+    ));
+
+    let options = AnalyzeOptions {
+        allow_undeclared_sources: true,
+        ..Default::default()
+    };
+    let network = analyze_program_with_options(code, &HashMap::new(), &options)
+        .unwrap()
+        .network;
+    assert_eq!(
+        network.format_node(network.root),
+        "divide(flip(data(\"vwap\")), data(\"close\"))"
+    );
+    assert!(network.inputs().iter().any(|i| matches!(
+        i,
+        Input::Source { name, .. } if name == "vwap"
+    )));
+
+    // Variables the flow assigns are still checked.
+    let mut pairs = CometParser::parse(Rule::program, "Flow f { x = flip(x)\n x }").unwrap();
+    let code = parse_program(pairs.next().unwrap()).unwrap();
+    assert!(matches!(
+        analyze_program_with_options(code, &HashMap::new(), &options),
+        Err(ParserError::UseBeforeDefinition { .. })
+    ));
+}
+
+#[test]
+fn test_parse_scale() {
+    let (network, _) = parse("Flow f { x = data(\"close\")\n scale(rank(x)) }").unwrap();
+    assert_eq!(network.to_sexpr(), "(scale (rank (data \"close\")))");
+}
+
+#[test]
+fn test_parse_behavior_default_arguments() {
+    use crate::expr::Literal;
+
+    let input = r#"
+        Behavior Smoother(signal: DataFrame, window: Int = 21, reference: DataFrame = data("close")) {
+            operators = [ts_mean], integers = [], floats = [], strings = []
+        } -> DataFrame
+
+        Flow f {
+            Smoother(data("volume"))
+        }
+    "#;
+    let (network, _) = parse(input).unwrap();
+    let (_, decl) = network.get_behavior();
+    assert_eq!(
+        decl.defaults,
+        vec![
+            None,
+            Some(Expr::Literal(Literal::Integer(21))),
+            Some(Expr::Call {
+                fn_name: "data".to_string(),
+                args: vec![Expr::Literal(Literal::String("close".to_string()))],
+            }),
+        ]
+    );
+    assert_eq!(
+        network.format_node(network.root),
+        "Smoother(data(\"volume\"), 21, data(\"close\"))"
+    );
+
+    // An argument without a default cannot be omitted.
+    assert!(parse(&input.replace("Smoother(data(\"volume\"))", "Smoother()")).is_err());
+    // Only trailing arguments can have defaults.
+    assert!(parse("Behavior B(a: Int = 1, b: Int) -> DataFrame\nFlow f { B(1, 2) }").is_err());
+}
+
+#[test]
+fn test_unknown_param_type() {
+    let input = r#"
+        Behavior Mix(signal: DataFrame, window: Integr) -> DataFrame
+        Flow f { Mix(data("close"), 5) }
+    "#;
+    match parse(input) {
+        Err(ParserError::UnknownParamType { func, param, ty }) => {
+            assert_eq!(
+                (func.as_str(), param.as_str(), ty.as_str()),
+                ("Mix", "window", "Integr")
+            );
+        }
+        other => panic!(
+            "Expected unknown param type, got {:?}",
+            other.map(|(n, _)| n)
+        ),
+    }
+}
+
+#[test]
+fn test_symbol_table() {
+    let input = r#"
+        Behavior Generator() {
+            operators = [add], integers = [], floats = [], strings = [], category = "source"
+        } -> DataFrame
+
+        Behavior Mix(a: DataFrame, window: Int = 5) {
+            operators = [ts_mean], integers = [5, 21], floats = [], strings = [], priority = 2
+        } -> DataFrame
+
+        Flow volume_ratio Implements Generator {
+            divide(data("volume"), data("adv20"))
+        }
+
+        Export Flow strategy {
+            Mix(Generator())
+        }
+    "#;
+    let mut pairs = CometParser::parse(Rule::program, input).unwrap();
+    let code = parse_program(pairs.next().unwrap()).unwrap();
+    let table = symbol_table(&code);
+    assert!(table.contains("  Mix(DataFrame, Int = 5) -> DataFrame\n"));
+    assert!(table.contains("    priority = 2\n"));
+    assert!(table.contains("    integers = [5, 21]\n"));
+    assert!(table.contains("    category = \"source\"\n"));
+    assert!(table.contains("  volume_ratio implements Generator\n"));
+    assert!(table.ends_with("  strategy (exported)"));
+}
+
+#[test]
+fn test_parse_lambda() {
+    let input = r#"
+        Flow f {
+            close = data("close")
+            prices = data("open")
+            apply(x => divide(x, close), prices)
+        }
+    "#;
+    let mut pairs = CometParser::parse(Rule::program, input).unwrap();
+    let code = parse_program(pairs.next().unwrap()).unwrap();
+    match &code[0] {
+        InputDecl::Flow(f) => {
+            let FlowStmt::Expr(Expr::Call { args, .. }) = f.body.last().unwrap() else {
+                panic!("Expected call");
+            };
+            assert_eq!(
+                args[0],
+                Expr::Lambda {
+                    params: vec!["x".to_string()],
+                    body: Box::new(Expr::Call {
+                        fn_name: "divide".to_string(),
+                        args: vec![
+                            Expr::Identifier("x".to_string()),
+                            Expr::Identifier("close".to_string()),
+                        ],
+                    }),
+                }
+            );
+            assert_eq!(args[0].to_string(), "x => divide(x, close)");
+        }
+        _ => panic!("Expected flow"),
+    }
+
+    let (network, _) = parse(input).unwrap();
+    assert_eq!(
+        network.format_node(network.root),
+        "divide(data(\"open\"), data(\"close\"))"
+    );
+
+    // Several parameters, and a lambda bound to a variable.
+    let (network, _) = parse(
+        "Flow f { spread = (a, b) => subtract(a, b)\n apply(spread, data(\"high\"), data(\"low\")) }",
+    )
+    .unwrap();
+    assert_eq!(
+        network.format_node(network.root),
+        "subtract(data(\"high\"), data(\"low\"))"
+    );
+
+    assert!(parse("Flow f { apply(x => flip(x), data(\"a\"), data(\"b\")) }").is_err());
+    assert!(parse("Flow f { flip(x => x) }").is_err());
+
+    // A behavior named apply is called like any other behavior.
+    let (network, _) =
+        parse("Behavior apply(x: DataFrame) -> DataFrame\nFlow f { apply(data(\"close\")) }")
+            .unwrap();
+    assert_eq!(network.format_node(network.root), "apply(data(\"close\"))");
+}
+
+#[test]
+fn test_analysis_warnings() {
+    let input = r#"
+        Flow f {
+            x = data("close")
+            unused = data("open")
+            flip(x)
+            rank(x)
+        }
+    "#;
+    let mut pairs = CometParser::parse(Rule::program, input).unwrap();
+    let code = parse_program(pairs.next().unwrap()).unwrap();
+    let analysis =
+        analyze_program_with_options(code, &HashMap::new(), &AnalyzeOptions::default()).unwrap();
+    assert_eq!(
+        analysis.network.format_node(analysis.network.root),
+        "rank(data(\"close\"))"
+    );
+    assert_eq!(
+        analysis.warnings,
+        vec![
+            Warning::UnusedVariable {
+                flow: "f".to_string(),
+                var: "unused".to_string(),
+            },
+            Warning::UnusedExpression {
+                flow: "f".to_string(),
+                stmt_index: 2,
+            },
+        ]
+    );
+
+    let options = AnalyzeOptions {
+        deny_warnings: true,
+        ..Default::default()
+    };
+    let code = parse_source(input).unwrap();
+    match analyze_program_with_options(code, &HashMap::new(), &options) {
+        Err(ParserError::DeniedWarnings(warnings)) => assert_eq!(warnings, analysis.warnings),
+        other => panic!(
+            "expected denied warnings, got {:?}",
+            other.map(|a| a.warnings)
+        ),
+    }
+}
+
+#[test]
+fn test_parse_quantile() {
+    let (network, _) =
+        parse("Flow f { x = data(\"close\")\n subtract(x, quantile(x, 0.9)) }").unwrap();
+    assert_eq!(
+        network.to_sexpr(),
+        "(subtract (data \"close\") (quantile (data \"close\") 0.9))"
+    );
+
+    assert!(parse("Flow f { quantile(data(\"close\"), 1.5) }").is_err());
+    assert!(parse("Flow f { quantile(data(\"close\"), data(\"q\")) }").is_err());
+    // Int literals are not widened, quantile takes a Float.
+    assert!(parse("Flow f { quantile(data(\"close\"), 1) }").is_err());
+}
+
+#[test]
+fn test_allowed_sources() {
+    let input = r#"
+        Flow f {
+            x = data("close")
+            divide(x, data("volume"))
+        }
+    "#;
+    let mut pairs = CometParser::parse(Rule::program, input).unwrap();
+    let code = parse_program(pairs.next().unwrap()).unwrap();
+
+    let mut options = AnalyzeOptions {
+        allowed_sources: Some(["close".to_string()].into_iter().collect()),
+        ..Default::default()
+    };
+    assert!(matches!(
+        analyze_program_with_options(code.clone(), &HashMap::new(), &options),
+        Err(ParserError::ForbiddenSource(name)) if name == "volume"
+    ));
+
+    options.allowed_sources = Some(
+        ["close", "volume"]
+            .into_iter()
+            .map(|s| s.to_string())
+            .collect(),
+    );
+    assert!(analyze_program_with_options(code, &HashMap::new(), &options).is_ok());
+
+    // The strings of a behavior searching with data are sources too.
+    let code = parse_source(
+        r#"
+        Behavior Pick() { operators = [data, rank], strings = ["close", "vwap"] } -> DataFrame
+        Flow f { Pick() }
+    "#,
+    )
+    .unwrap();
+    assert!(matches!(
+        analyze_program_with_options(code.clone(), &HashMap::new(), &options),
+        Err(ParserError::ForbiddenSource(name)) if name == "vwap"
+    ));
+    options.allowed_sources = Some(
+        ["close", "vwap"]
+            .into_iter()
+            .map(|s| s.to_string())
+            .collect(),
+    );
+    assert!(analyze_program_with_options(code, &HashMap::new(), &options).is_ok());
+
+    // Without data they are plain arguments.
+    let code = parse_source(
+        r#"
+        Behavior Pick() { operators = [rank], strings = ["vwap"] } -> DataFrame
+        Flow f { Pick() }
+    "#,
+    )
+    .unwrap();
+    options.allowed_sources = Some(["close".to_string()].into_iter().collect());
+    assert!(analyze_program_with_options(code, &HashMap::new(), &options).is_ok());
+}
+
+#[test]
+fn test_explain_empty() {
+    let input = r#"
+        Behavior Smoother(signal: DataFrame) {
+            operators = [ts_mean, rank], integers = [], floats = [], strings = []
+        } -> DataFrame
+    "#;
+    let code = parse_source(input).unwrap();
+    let InputDecl::Behavior(b) = &code[0] else {
+        panic!("Expected behavior");
+    };
+    assert_eq!(
+        b.explain_empty(),
+        vec![
+            "Smoother: ts_mean(DataFrame, Int) needs an argument of type Int, which Smoother cannot supply"
+        ]
+    );
+
+    let mut fixed = b.clone();
+    fixed.integers = Some(vec![5]);
+    assert!(fixed.explain_empty().is_empty());
+}
+
+#[test]
+fn test_parse_file_reexport() {
+    let tmp = crate::testing::TempDir::new("reexport");
+    let dir = tmp.path();
+    std::fs::create_dir_all(dir.join("internal")).unwrap();
+    std::fs::write(
+        dir.join("internal/stats.cm"),
+        r#"
+        Behavior Normalizer(signal: DataFrame) {
+            operators = [rank, cs_zscore], integers = [], floats = [], strings = []
+        } -> DataFrame
+        "#,
+    )
+    .unwrap();
+    std::fs::write(dir.join("facade.cm"), "Export Import \"internal/stats.cm\"").unwrap();
+    std::fs::write(dir.join("api.cm"), "Export Import \"facade.cm\"").unwrap();
+    std::fs::write(dir.join("private.cm"), "Import \"internal/stats.cm\"").unwrap();
+    let flow = "Flow f {\n Normalizer(data(\"close\"))\n }";
+
+    std::fs::write(dir.join("main.cm"), format!("Import \"api.cm\"\n{}", flow)).unwrap();
+    let (network, behavior_nodes) = parse_file(&dir.join("main.cm")).unwrap();
+    assert_eq!(
+        network.format_node(network.root),
+        "Normalizer(data(\"close\"))"
+    );
+    assert_eq!(behavior_nodes, vec![network.root]);
+
+    // A plain import stays local to the file that wrote it.
+    std::fs::write(
+        dir.join("local.cm"),
+        format!("Import \"private.cm\"\n{}", flow),
+    )
+    .unwrap();
+    assert!(parse_file(&dir.join("local.cm")).is_err());
+
+    let code = parse_source("Export Import \"facade.cm\"").unwrap();
+    assert_eq!(
+        code,
+        vec![InputDecl::Import(ImportDecl {
+            path: "facade.cm".to_string(),
+            reexport: true,
+            feature: None,
+            symbols: None,
+        })]
+    );
+}
+
+#[test]
+fn test_impl_order() {
+    let input = r#"
+        Behavior Helper() {
+            operators = [rank], integers = [], floats = [], strings = []
+        } -> DataFrame
+        Behavior Spread() {
+            operators = [subtract], integers = [], floats = [], strings = []
+        } -> DataFrame
+        Behavior Signal() {
+            operators = [divide], integers = [], floats = [], strings = []
+        } -> DataFrame
+
+        Flow signal Implements Signal {
+            divide(Spread(), Helper())
+        }
+        Flow spread Implements Spread {
+            subtract(Helper(), data("open"))
+        }
+        Flow helper Implements Helper {
+            rank(data("close"))
+        }
+        Flow strategy {
+            Signal()
+        }
+    "#;
+    let code = parse_source(input).unwrap();
+    let flows: Vec<FlowDecl> = code
+        .iter()
+        .filter_map(|d| match d {
+            InputDecl::Flow(f) if f.implements.is_some() => Some(f.clone()),
+            _ => None,
+        })
+        .collect();
+    let impl_flows: HashMap<&str, &FlowDecl> = flows
+        .iter()
+        .map(|f| (f.implements.as_deref().unwrap(), f))
+        .collect();
+    assert_eq!(
+        impl_order(&impl_flows).unwrap(),
+        vec!["Helper", "Spread", "Signal"]
+    );
+
+    let (network, _) = parse(input).unwrap();
+    assert_eq!(
+        network.format_node(network.root),
+        "divide(subtract(rank(data(\"close\")), data(\"open\")), rank(data(\"close\")))"
+    );
+
+    // A cycle is an error even when the output flow never calls into it.
+    let cyclic = input
+        .replace("rank(data(\"close\"))", "rank(Signal())")
+        .replace(
+            "Flow strategy {\n            Signal()",
+            "Flow strategy {\n            data(\"close\")",
+        );
+    match parse(&cyclic) {
+        Err(ParserError::RecursiveImpl { chain }) => {
+            assert_eq!(chain, vec!["Helper", "Signal", "Spread", "Helper"]);
+        }
+        other => panic!("Expected recursive impl, got {:?}", other.map(|(n, _)| n)),
+    }
+}
+
+#[test]
+fn test_why_no_match() {
+    let input = r#"
+        Behavior Smoother(signal: DataFrame, window: Int = 21) {
+            operators = [ts_mean], integers = [5], floats = [], strings = []
+        } -> DataFrame
+    "#;
+    let code = parse_source(input).unwrap();
+    let df = Signal::DataFrame(None);
+
+    assert!(why_no_match(&code, "Smoother", std::slice::from_ref(&df)).is_empty());
+    assert!(why_no_match(&code, "Smoother", &[df.clone(), Signal::Int(None)]).is_empty());
+    assert_eq!(
+        why_no_match(&code, "Smoother", &[df.clone(), Signal::Float(None)]),
+        vec!["Smoother(DataFrame, Int = 21) -> DataFrame: argument 2 is Float, expected Int"]
+    );
+    assert_eq!(
+        why_no_match(&code, "Smoother", &[]),
+        vec!["Smoother(DataFrame, Int = 21) -> DataFrame: expects 1 to 2 arguments, got 0"]
+    );
+    assert_eq!(
+        why_no_match(&code, "ts_mean", &[Signal::Int(None), df]),
+        vec!["ts_mean(DataFrame, Int) -> DataFrame: argument 1 is Int, expected DataFrame"]
+    );
+    assert_eq!(
+        why_no_match(&code, "missing", &[]),
+        vec!["No behavior or operator named missing"]
+    );
+}
+
+#[test]
+fn test_parse_type_annotation() {
+    let input = r#"
+        Flow f {
+            ma: DataFrame = ts_mean(data("close"), 20)
+            window: Int = 20
+            divide(data("close"), ma)
+        }
+    "#;
+    let code = parse_source(input).unwrap();
+    let InputDecl::Flow(f) = &code[0] else {
+        panic!("Expected flow");
+    };
+    assert_eq!(
+        f.body[0],
+        FlowStmt::Assignment {
+            target: "ma".to_string(),
+            ty: Some(Signal::DataFrame(None)),
+            expr: Expr::Call {
+                fn_name: "ts_mean".to_string(),
+                args: vec![
+                    Expr::Call {
+                        fn_name: "data".to_string(),
+                        args: vec![Expr::Literal(crate::expr::Literal::String(
+                            "close".to_string()
+                        ))],
+                    },
+                    Expr::Literal(crate::expr::Literal::Integer(20)),
+                ],
+            },
+        }
+    );
+    assert_eq!(f.body[1].to_string(), "window: Int = 20");
+    assert!(parse(input).is_ok());
+
+    match parse("Flow f {\n ma: Int = ts_mean(data(\"close\"), 20)\n flip(ma)\n }") {
+        Err(ParserError::AnnotationMismatch {
+            var,
+            expected,
+            found,
+            stmt_index,
+        }) => {
+            assert_eq!(stmt_index, 0);
+            assert_eq!(var, "ma");
+            assert_eq!(expected, "Int");
+            assert_eq!(found, "DataFrame");
+        }
+        other => panic!(
+            "Expected annotation mismatch, got {:?}",
+            other.map(|(n, _)| n)
+        ),
+    }
+}
+
+#[test]
+fn test_parse_returns() {
+    let (network, _) =
+        parse("Flow f { close = data(\"close\")\n rank(returns(close, 1)) }").unwrap();
+    assert_eq!(network.to_sexpr(), "(rank (ts_return (data \"close\") 1))");
+    assert!(parse("Flow f { returns(data(\"close\"), 5d) }").is_ok());
+
+    for period in ["0", "-1", "1.5", "data(\"n\")"] {
+        let input = format!("Flow f {{ returns(data(\"close\"), {}) }}", period);
+        assert!(parse(&input).is_err(), "period {}", period);
+    }
+}
+
+#[test]
+fn test_shadowed_variable_warning() {
+    let input = r#"
+        Flow f {
+            x = data("close")
+            x = data("open")
+            result = rank(x)
+            result = flip(x)
+            result
+        }
+    "#;
+    let code = parse_source(input).unwrap();
+    let analysis =
+        analyze_program_with_options(code.clone(), &HashMap::new(), &AnalyzeOptions::default())
+            .unwrap();
+    let shadowed: Vec<&Warning> = analysis
+        .warnings
+        .iter()
+        .filter(|w| matches!(w, Warning::ShadowedVariable { .. }))
+        .collect();
+    assert_eq!(
+        shadowed,
+        vec![&Warning::ShadowedVariable {
+            flow: "f".to_string(),
+            name: "x".to_string(),
+            stmt_index: 1,
+        }]
+    );
+
+    let options = AnalyzeOptions {
+        allow_shadowing: true,
+        ..Default::default()
+    };
+    let analysis = analyze_program_with_options(code, &HashMap::new(), &options).unwrap();
+    assert!(
+        !analysis
+            .warnings
+            .iter()
+            .any(|w| matches!(w, Warning::ShadowedVariable { .. }))
+    );
+}
+
+#[test]
+fn test_registered_operator() {
+    static OP_DOUBLE: OperatorSpec = OperatorSpec {
+        name: "test_double",
+        inputs: &[Signal::DataFrame(None)],
+        output_shape: Signal::DataFrame(None),
+        execute: |args| match &args[0] {
+            Signal::DataFrame(Some(a)) => Signal::DataFrame(Some(a * 2.0)),
+            _ => panic!("test_double expected DataFrame"),
+        },
+    };
+
+    assert!(matches!(
+        parse("Flow f { test_double(data(\"close\")) }"),
+        Err(ParserError::UnknownOperators(_))
+    ));
+    OperatorSpec::register(&OP_DOUBLE).unwrap();
+    assert!(OperatorSpec::register(&OP_DOUBLE).is_err());
+
+    let (network, _) = parse("Flow f { rank(test_double(data(\"close\"))) }").unwrap();
+    assert_eq!(network.to_sexpr(), "(rank (test_double (data \"close\")))");
+    assert!(matches!(
+        &network.nodes[network.nodes[network.root].children[0]].node_type,
+        NodeType::Operator(spec) if std::ptr::eq(*spec, &OP_DOUBLE)
+    ));
+}
+
+#[test]
+fn test_load_program_from_reader() {
+    let tmp = crate::testing::TempDir::new("stdin");
+    let dir = tmp.path();
+    std::fs::write(
+        dir.join("lib.cm"),
+        r#"
+        Behavior Normalizer(signal: DataFrame) {
+            operators = [rank], integers = [], floats = [], strings = []
+        } -> DataFrame
+        "#,
+    )
+    .unwrap();
+    let src = "Import \"lib.cm\"\nFlow f {\n Normalizer(data(\"close\"))\n }";
+    let code = load_program_from_reader(src.as_bytes(), dir, &HashSet::new()).unwrap();
+    let (network, behavior_nodes) = analyze_program(code).unwrap();
+    assert_eq!(
+        network.format_node(network.root),
+        "Normalizer(data(\"close\"))"
+    );
+    assert_eq!(behavior_nodes, vec![network.root]);
+
+    assert!(load_program_from_reader("Flow f {".as_bytes(), dir, &HashSet::new()).is_err());
+}
+
+#[test]
+fn test_parse_rolling_windows() {
+    for op in ["ts_sum", "ts_max", "ts_min"] {
+        let (network, _) = parse(&format!("Flow f {{ {}(data(\"high\"), 20) }}", op)).unwrap();
+        assert_eq!(network.to_sexpr(), format!("({} (data \"high\") 20)", op));
+        let (network, _) = parse(&format!("Flow f {{ {}(data(\"high\"), 5d) }}", op)).unwrap();
+        assert_eq!(network.to_sexpr(), format!("({} (data \"high\") 5d)", op));
+
+        match parse(&format!("Flow f {{ {}(data(\"high\"), 0) }}", op)) {
+            Err(ParserError::SemanticError(msg)) => assert!(msg.contains("must be positive")),
+            other => panic!("Expected window error, got {:?}", other.map(|(n, _)| n)),
+        }
+    }
+}
+
+#[test]
+fn test_applicable_behaviors() {
+    let input = r#"
+        Behavior Smoother(signal: DataFrame, window: Int) {
+            operators = [ts_mean], integers = [5], floats = [], strings = []
+        } -> DataFrame
+        Behavior Lookback(window: Int) {
+            operators = [delay], integers = [5], floats = [], strings = []
+        } -> DataFrame
+        Behavior Generator() {
+            operators = [add], integers = [], floats = [], strings = []
+        } -> DataFrame
+        Behavior Ratio(signal: DataFrame, reference: DataFrame) {
+            operators = [divide], integers = [], floats = [], strings = []
+        } -> DataFrame
+    "#;
+    let code = parse_source(input).unwrap();
+    assert_eq!(
+        applicable_behaviors(&code, &Signal::DataFrame(None)),
+        vec!["Smoother", "Ratio"]
+    );
+    assert_eq!(
+        applicable_behaviors(&code, &Signal::Int(None)),
+        vec!["Lookback"]
+    );
+    assert!(applicable_behaviors(&code, &Signal::Float(None)).is_empty());
+}
+
+#[test]
+fn test_parse_nullary_behavior_as_value() {
+    let input = r#"
+        Behavior Generator() {
+            operators = [add], integers = [], floats = [], strings = []
+        } -> DataFrame
+        Behavior Smoother(signal: DataFrame, window: Int = 21) {
+            operators = [ts_mean], integers = [5], floats = [], strings = []
+        } -> DataFrame
+        Flow f {
+            y: DataFrame = Generator
+            rank(y)
+        }
+    "#;
+    let (network, behavior_nodes) = analyze_program(parse_source(input).unwrap()).unwrap();
+    assert_eq!(network.format_node(network.root), "rank(Generator())");
+    assert_eq!(behavior_nodes.len(), 1);
+
+    // A behavior with a required argument is not a value.
+    let input = input.replace("= Generator", "= Smoother");
+    assert!(analyze_program(parse_source(&input).unwrap()).is_err());
+
+    // A nullary behavior implemented by a flow inlines the flow, as a call would.
+    let input = r#"
+        Behavior Close() -> DataFrame
+        Flow close Implements Close { data("close") }
+        Flow f { flip(Close) }
+    "#;
+    let (network, _) = analyze_program(parse_source(input).unwrap()).unwrap();
+    assert_eq!(network.format_node(network.root), "flip(data(\"close\"))");
+}
+
+#[test]
+fn test_deprecated_behavior() {
+    let input = r#"
+        @deprecated("use Smoother")
+        Behavior OldSmoother(signal: DataFrame) {
+            operators = [ts_mean], integers = [5], floats = [], strings = []
+        } -> DataFrame
+        Behavior Smoother(signal: DataFrame) {
+            operators = [ts_mean], integers = [5], floats = [], strings = []
+        } -> DataFrame
+        Flow f { rank(OldSmoother(data("close"))) }
+    "#;
+    let code = parse_source(input).unwrap();
+    let InputDecl::Behavior(old) = &code[0] else {
+        panic!("expected a behavior");
+    };
+    assert_eq!(old.deprecated.as_deref(), Some("use Smoother"));
+    assert!(symbol_table(&code).contains("@deprecated(\"use Smoother\")"));
+
+    let analysis =
+        analyze_program_with_options(code, &HashMap::new(), &AnalyzeOptions::default()).unwrap();
+    assert_eq!(
+        analysis.warnings,
+        vec![Warning::DeprecatedBehavior {
+            name: "OldSmoother".to_string(),
+            reason: "use Smoother".to_string(),
+        }]
+    );
+
+    let unused = input.replace("rank(OldSmoother(", "rank(Smoother(");
+    let analysis = analyze_program_with_options(
+        parse_source(&unused).unwrap(),
+        &HashMap::new(),
+        &AnalyzeOptions::default(),
+    )
+    .unwrap();
+    assert!(analysis.warnings.is_empty());
+
+    assert!(parse_source(&input.replace("@deprecated", "@obsolete")).is_err());
+}
+
+#[test]
+fn test_parse_trailing_commas() {
+    let input = r#"
+        Behavior Mix(a: DataFrame, window: Int = 5,) {
+            operators = [add, ts_mean,], integers = [5, 21,], floats = [0.5,], priority = 2,
+        } -> DataFrame
+        Flow f {
+            close = data("close",)
+            apply((x, y,) => divide(x, y,), Mix(close,), close,)
+        }
+    "#;
+    // Statement spans shift with the removed commas, so only the declarations are compared.
+    let without_spans = |code: InputCode| -> InputCode {
+        code.into_iter()
+            .map(|decl| match decl {
+                InputDecl::Flow(f) => InputDecl::Flow(FlowDecl { spans: vec![], ..f }),
+                other => other,
+            })
+            .collect()
+    };
+    let with_commas = without_spans(parse_source(input).unwrap());
+    let stripped = input
+        .replace(",)", ")")
+        .replace(",]", "]")
+        .replace(",\n", "\n");
+    let without_commas = without_spans(parse_source(&stripped).unwrap());
+    assert_eq!(with_commas, without_commas);
+
+    // A lone comma is not an empty list.
+    assert!(parse_source(r#"Flow f { rank(,) }"#).is_err());
+}
+
+#[test]
+fn test_operator_table() {
+    let table = operator_table();
+    let lines: Vec<&str> = table.lines().collect();
+    assert!(lines.contains(&"divide(DataFrame, DataFrame) -> DataFrame"));
+    assert!(lines.contains(&"ts_mean(DataFrame, Int) -> DataFrame"));
+    assert!(lines.contains(&"data(String) -> DataFrame"));
+    assert!(lines.contains(&"rank(DataFrame) -> DataFrame"));
+}
+
+#[test]
+fn test_parse_feature_import() {
+    let tmp = crate::testing::TempDir::new("feature_import");
+    let dir = tmp.path();
+    std::fs::write(
+        dir.join("debug.cm"),
+        "Behavior Probe(a: DataFrame) { operators = [rank] } -> DataFrame",
+    )
+    .unwrap();
+    let main = dir.join("main.cm");
+    std::fs::write(
+        &main,
+        "Import \"debug.cm\" If debug\nFlow f { rank(data(\"close\")) }",
+    )
+    .unwrap();
+
+    let behaviors = |code: &InputCode| -> Vec<String> {
+        code.iter()
+            .filter_map(|decl| match decl {
+                InputDecl::Behavior(b) => b.name.clone(),
+                _ => None,
+            })
+            .collect()
+    };
+    assert!(behaviors(&load_program(&main).unwrap()).is_empty());
+    let other: HashSet<String> = ["release".to_string()].into();
+    assert!(behaviors(&load_program_with_features(&main, &other).unwrap()).is_empty());
+    let debug: HashSet<String> = ["debug".to_string()].into();
+    assert_eq!(
+        behaviors(&load_program_with_features(&main, &debug).unwrap()),
+        vec!["Probe"]
+    );
+
+    let code = parse_source("Import \"debug.cm\" If debug").unwrap();
+    assert_eq!(
+        code,
+        vec![InputDecl::Import(ImportDecl {
+            path: "debug.cm".to_string(),
+            reexport: false,
+            feature: Some("debug".to_string()),
+            symbols: None,
+        })]
+    );
+}
+
+#[test]
+fn test_unreachable_impl_warning() {
+    let input = r#"
+        Behavior Volume() -> DataFrame
+        Behavior Close() -> DataFrame
+        Behavior Ratio() -> DataFrame
+        Flow volume Implements Volume { data("volume") }
+        Flow close Implements Close { data("close") }
+        Flow ratio Implements Ratio { divide(Volume(), data("adv20")) }
+        Flow f { rank(Ratio()) }
+    "#;
+    let analysis = analyze_program_with_options(
+        parse_source(input).unwrap(),
+        &HashMap::new(),
+        &AnalyzeOptions::default(),
+    )
+    .unwrap();
+    // volume is only reached through ratio, which still counts.
+    assert_eq!(
+        analysis.warnings,
+        vec![Warning::UnreachableImpl {
+            name: "close".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn test_orphan_nodes() {
+    // Every built node is wired into its call, through impls, apply and returns alike.
+    let input = r#"
+        Behavior Close() -> DataFrame
+        Flow close Implements Close { data("close") }
+        Flow f {
+            momentum = returns(Close(), 5)
+            apply((x) => rank(x), momentum)
+        }
+    "#;
+    let options = AnalyzeOptions {
+        check_orphans: true,
+        ..Default::default()
+    };
+    let analysis =
+        analyze_program_with_options(parse_source(input).unwrap(), &HashMap::new(), &options)
+            .unwrap();
+    assert_eq!(analysis.warnings, vec![]);
+
+    // A handler that builds its argument and then leaves it out of the call.
+    let behaviors = HashMap::new();
+    let impl_bodies = HashMap::new();
+    let mut ctx = BuildContext {
+        behaviors: &behaviors,
+        impl_bodies: &impl_bodies,
+        behaviors_ptr: Vec::new(),
+        expanding: Vec::new(),
+        allow_undeclared_sources: false,
+        unknown_ops: Vec::new(),
+        deprecated: Vec::new(),
+        inlined: HashSet::new(),
+        failed_at: None,
+        check_orphans: true,
+        orphans: Vec::new(),
+    };
+    let expr = Expr::Call {
+        fn_name: "rank".to_string(),
+        args: vec![Expr::Call {
+            fn_name: "data".to_string(),
+            args: vec![Expr::Literal(crate::expr::Literal::String(
+                "close".to_string(),
+            ))],
+        }],
+    };
+    let Expr::Call { args, .. } = &expr else {
+        unreachable!()
+    };
+    let mut network = Network::new();
+    build_ast(&mut network, &args[0], &HashMap::new(), &mut ctx, true).unwrap();
+    let node_id = network.add_node(NodeType::Operator("rank".into()));
+    report_orphans(&network, 0, node_id, &expr, &mut ctx);
+    assert_eq!(ctx.orphans, vec![(expr.to_string(), vec![0, 1])]);
+
+    // The enclosing expression does not report the same nodes again.
+    report_orphans(&network, 0, node_id, &expr, &mut ctx);
+    assert_eq!(ctx.orphans.len(), 1);
+}
+
+#[test]
+fn test_parse_import_symbols() {
+    let code = parse_source("Export Import \"stats.cm\" { Zscore, Rank, } If stats").unwrap();
+    assert_eq!(
+        code,
+        vec![InputDecl::Import(ImportDecl {
+            path: "stats.cm".to_string(),
+            reexport: true,
+            feature: Some("stats".to_string()),
+            symbols: Some(vec!["Zscore".to_string(), "Rank".to_string()]),
+        })]
+    );
+    assert!(parse_source("Import \"stats.cm\" { }").is_err());
+}
+
+#[test]
+fn test_missing_imported_symbol() {
+    let tmp = crate::testing::TempDir::new("import_symbols");
+    let dir = tmp.path();
+    std::fs::write(
+        dir.join("stats.cm"),
+        "Behavior Zscore(a: DataFrame) { operators = [cs_zscore] } -> DataFrame",
+    )
+    .unwrap();
+    let main = dir.join("main.cm");
+    std::fs::write(
+        &main,
+        "Import \"stats.cm\" { Zscore }\nFlow f { Zscore(data(\"close\")) }",
+    )
+    .unwrap();
+    assert!(load_program(&main).is_ok());
+
+    std::fs::write(
+        &main,
+        "Import \"stats.cm\" { Zscore, Rank }\nFlow f { Zscore(data(\"close\")) }",
+    )
+    .unwrap();
+    match load_program(&main) {
+        Err(ParserError::MissingImportedSymbol { module, symbol }) => {
+            assert_eq!(module, "stats.cm");
+            assert_eq!(symbol, "Rank");
+        }
+        other => panic!("Expected a missing symbol, got {:?}", other),
+    }
+
+    // Behaviors left out of the list are not imported.
+    std::fs::write(
+        dir.join("stats.cm"),
+        "Behavior Zscore(a: DataFrame) { operators = [cs_zscore] } -> DataFrame\n\
+         Behavior Rank(a: DataFrame) { operators = [rank] } -> DataFrame",
+    )
+    .unwrap();
+    std::fs::write(
+        &main,
+        "Import \"stats.cm\" { Zscore }\nFlow f { Rank(data(\"close\")) }",
+    )
+    .unwrap();
+    assert!(parse_file(&main).is_err());
+
+    // Within an import cycle, the file still being loaded is not checked.
+    std::fs::write(
+        dir.join("a.cm"),
+        "Import \"b.cm\" { B }\nBehavior A(x: DataFrame) { operators = [rank] } -> DataFrame\n\
+         Flow f { A(B(data(\"close\"))) }",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("b.cm"),
+        "Import \"a.cm\" { A }\nBehavior B(x: DataFrame) { operators = [flip] } -> DataFrame",
+    )
+    .unwrap();
+    assert!(parse_file(&dir.join("a.cm")).is_ok());
+}
+
+#[test]
+fn test_parse_doc_comment() {
+    let input = r#"
+        // A plain comment is not documentation.
+        /// Smooths a signal over a week.
+        ///
+        /// Searches moving averages only.
+        @deprecated("use Smoother")
+        Behavior Weekly(signal: DataFrame) { operators = [ts_mean], integers = [5] } -> DataFrame
+        //// Four slashes are a plain comment too.
+        Behavior Plain(signal: DataFrame) { operators = [rank] } -> DataFrame
+        Flow f { Plain(Weekly(data("close"))) }
+    "#;
+    let code = parse_source(input).unwrap();
+    let docs: Vec<Option<&str>> = code
+        .iter()
+        .filter_map(|decl| match decl {
+            InputDecl::Behavior(b) => Some(b.doc.as_deref()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        docs,
+        vec![
+            Some("Smooths a signal over a week.\n\nSearches moving averages only."),
+            None
+        ]
+    );
+    assert!(symbol_table(&code).contains("    /// Smooths a signal over a week.\n"));
+
+    // The documentation travels with the behavior node into the CIR.
+    let (network, _) = parse(input).unwrap();
+    assert!(network.to_cir().contains("Smooths a signal over a week."));
+
+    // Anywhere else /// is an ordinary comment.
+    let input = r#"
+        /// The output.
+        Flow f {
+            x = data("close") /// trailing
+            rank(x)
+        }
+        /// The import.
+        Import "other.cm"
+    "#;
+    let code = parse_source(input).unwrap();
+    assert!(matches!(&code[0], InputDecl::Flow(f) if f.body.len() == 2));
+    assert!(matches!(&code[1], InputDecl::Import(_)));
+}
+
+#[test]
+fn test_parse_winsorize() {
+    let (network, _) = parse("Flow f { winsorize(data(\"close\"), 0.01, 0.99) }").unwrap();
+    assert_eq!(network.to_sexpr(), "(winsorize (data \"close\") 0.01 0.99)");
+    assert!(parse("Flow f { winsorize(data(\"close\"), 0.0, 1.0) }").is_ok());
+
+    for (bad, expected) in [
+        (
+            "winsorize(data(\"close\"), 0.99, 0.01)",
+            "Lower quantile 0.99 is not below the upper quantile 0.01",
+        ),
+        (
+            "winsorize(data(\"close\"), 0.5, 0.5)",
+            "Lower quantile 0.5 is not below the upper quantile 0.5",
+        ),
+        (
+            "winsorize(data(\"close\"), 0.1, 1.5)",
+            "Quantile 1.5 is outside [0, 1]",
+        ),
+        (
+            "winsorize(data(\"close\"), 0.1, data(\"q\"))",
+            "Quantile must be a constant",
+        ),
+        (
+            "winsorize(data(\"close\"), 0, 1)",
+            "Quantile must be a Float, write 0.0",
+        ),
+    ] {
+        match parse(&format!("Flow f {{ {} }}", bad)) {
+            Err(ParserError::SemanticError(msg)) => assert!(msg.starts_with(expected), "{}", msg),
+            other => panic!("Expected {:?}, got {:?}", expected, other.map(|(n, _)| n)),
+        }
+    }
+}
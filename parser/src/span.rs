@@ -0,0 +1,218 @@
+// Source locations for diagnostics, as byte offsets into the .cm source.
+use crate::parser::{ParserError, Rule};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize, // exclusive
+}
+
+impl From<pest::Span<'_>> for Span {
+    fn from(span: pest::Span<'_>) -> Self {
+        Span {
+            start: span.start(),
+            end: span.end(),
+        }
+    }
+}
+
+impl Span {
+    // Where a pest error points. A position becomes a one character span.
+    pub fn of_pest_error(e: &pest::error::Error<Rule>) -> Span {
+        match e.location {
+            pest::error::InputLocation::Pos(p) => Span {
+                start: p,
+                end: p + 1,
+            },
+            pest::error::InputLocation::Span((start, end)) => Span { start, end },
+        }
+    }
+}
+
+impl ParserError {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ParserError::PestError(e) => Some(Span::of_pest_error(e)),
+            _ => None,
+        }
+    }
+}
+
+// An error with the span of the statement it was raised for, when that is known. The span is
+// an offset into the source of the flow holding the statement. The error is boxed to keep
+// results carrying a Diagnostic small.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub error: Box<ParserError>,
+    pub span: Option<Span>,
+}
+
+impl From<ParserError> for Diagnostic {
+    fn from(error: ParserError) -> Self {
+        let span = error.span();
+        Diagnostic {
+            error: Box::new(error),
+            span,
+        }
+    }
+}
+
+impl Diagnostic {
+    // render_error when the location is known, the bare message otherwise.
+    pub fn render(&self, source: &str) -> String {
+        match self.span {
+            Some(span) => render_error(source, span, &self.error.to_string()),
+            None => format!("error: {}", self.error),
+        }
+    }
+}
+
+// The line containing the start of the span, with the span underlined, e.g.
+//
+// error: Undefined identifier: bar
+//  --> 3:14
+//   |
+// 3 | result = foo(bar)
+//   |              ^^^
+//
+// A span running past the end of its first line is underlined to the end of that line.
+pub fn render_error(source: &str, span: Span, msg: &str) -> String {
+    // Spans of pest errors are one byte wide, which can end inside a multibyte character.
+    let mut start = span.start.min(source.len());
+    while !source.is_char_boundary(start) {
+        start -= 1;
+    }
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |i| start + i);
+    let line = source[line_start..line_end].trim_end_matches('\r');
+    let line_no = source[..line_start].matches('\n').count() + 1;
+    let column = source[line_start..start].chars().count();
+    let mut end = span.end.clamp(start, line_end);
+    while !source.is_char_boundary(end) {
+        end += 1;
+    }
+    let width = source[start..end].chars().count().max(1);
+
+    let gutter = " ".repeat(line_no.to_string().len());
+    format!(
+        "error: {}\n{}--> {}:{}\n{} |\n{} | {}\n{} | {}{}",
+        msg,
+        gutter,
+        line_no,
+        column + 1,
+        gutter,
+        line_no,
+        line,
+        gutter,
+        " ".repeat(column),
+        "^".repeat(width)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_error() {
+        let source = "Flow f {\n x = data(\"close\")\n result = foo(bar)\n}";
+        let start = source.find("bar").unwrap();
+        let rendered = render_error(
+            source,
+            Span {
+                start,
+                end: start + 3,
+            },
+            "Undefined identifier: bar",
+        );
+        assert_eq!(
+            rendered,
+            "error: Undefined identifier: bar\n --> 3:15\n  |\n3 |  result = foo(bar)\n  |               ^^^"
+        );
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[3].find("bar"), lines[4].find('^'));
+    }
+
+    #[test]
+    fn test_analysis_error_span() {
+        let source = "Flow f {\n x = data(\"close\")\n y = quantile(x, 2.0)\n rank(y)\n}";
+        let code = crate::parser::parse_source(source).unwrap();
+        let err = crate::parser::analyze_program_located(
+            code,
+            &std::collections::HashMap::new(),
+            &crate::parser::AnalyzeOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(*err.error, ParserError::SemanticError(_)));
+        let span = err.span.unwrap();
+        assert_eq!(&source[span.start..span.end], "y = quantile(x, 2.0)");
+        assert!(
+            err.render(source)
+                .starts_with("error: Semantic error: Quantile")
+        );
+
+        // An error in the output expression points at the output statement.
+        let source = "Flow f {\n x = data(\"close\")\n ts_sum(x, 0)\n}";
+        let code = crate::parser::parse_source(source).unwrap();
+        let err = crate::parser::analyze_program_located(
+            code,
+            &std::collections::HashMap::new(),
+            &crate::parser::AnalyzeOptions::default(),
+        )
+        .unwrap_err();
+        let span = err.span.unwrap();
+        assert_eq!(&source[span.start..span.end], "ts_sum(x, 0)");
+
+        // A mismatched annotation points at the annotated statement, without its comment.
+        let source = "Flow f {\n ma: Int = ts_mean(data(\"close\"), 20) // weekly\n flip(ma)\n}";
+        let code = crate::parser::parse_source(source).unwrap();
+        let err = crate::parser::analyze_program_located(
+            code,
+            &std::collections::HashMap::new(),
+            &crate::parser::AnalyzeOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(*err.error, ParserError::AnnotationMismatch { .. }));
+        let span = err.span.unwrap();
+        assert_eq!(
+            &source[span.start..span.end],
+            "ma: Int = ts_mean(data(\"close\"), 20)"
+        );
+
+        // Inside an implementing flow, the statement of that flow is reported.
+        let source = "Behavior B() -> DataFrame\nFlow b Implements B {\n bad = ts_sum(data(\"close\"), 0)\n rank(bad)\n}\nFlow f { flip(B()) }";
+        let code = crate::parser::parse_source(source).unwrap();
+        let err = crate::parser::analyze_program_located(
+            code,
+            &std::collections::HashMap::new(),
+            &crate::parser::AnalyzeOptions::default(),
+        )
+        .unwrap_err();
+        let span = err.span.unwrap();
+        assert_eq!(
+            &source[span.start..span.end],
+            "bad = ts_sum(data(\"close\"), 0)"
+        );
+    }
+
+    #[test]
+    fn test_pest_error_span() {
+        let source = "Flow f {\n flip(\n}";
+        let err = crate::parser::parse(source).unwrap_err();
+        let span = err.span().unwrap();
+        assert!(span.start <= source.len());
+        assert!(render_error(source, span, &err.to_string()).contains('^'));
+    }
+
+    #[test]
+    fn test_pest_error_span_multibyte() {
+        let source = "Flow f {\n x = data(\"close\") → 1\n}";
+        let err = crate::parser::parse(source).unwrap_err();
+        let rendered = render_error(source, err.span().unwrap(), "unexpected");
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[4].trim_start_matches(&['|', ' '][..]), "^");
+        assert_eq!(lines[3].chars().position(|c| c == '→'), lines[4].find('^'));
+    }
+}
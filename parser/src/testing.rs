@@ -0,0 +1,135 @@
+// Helpers for asserting on parsed and searched networks, and for test fixtures. Enabled with
+// the `testing` feature.
+use crate::ast::{Network, NodeType};
+use crate::behavior::BehaviorDecl;
+use crate::expr::Literal;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+// Panics unless one of the networks renders to the given S-expression (see Network::to_sexpr).
+pub fn assert_has_variant(networks: &[Network], sexpr: &str) {
+    let found: Vec<String> = networks.iter().map(|n| n.to_sexpr()).collect();
+    assert!(
+        found.iter().any(|s| s == sexpr),
+        "Variant {} not found. Variants:\n{}",
+        sexpr,
+        found.join("\n")
+    );
+}
+
+// Number of structurally distinct networks.
+pub fn variant_count(networks: &[Network]) -> usize {
+    networks
+        .iter()
+        .map(|n| n.to_sexpr())
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+// Builds expected networks without going through the parser.
+#[derive(Default)]
+pub struct NetworkBuilder {
+    network: Network,
+}
+
+impl NetworkBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lit(&mut self, literal: Literal) -> usize {
+        self.network.add_node(NodeType::Literal(literal))
+    }
+
+    pub fn op(&mut self, name: &str, children: &[usize]) -> usize {
+        let idx = self.network.add_node(NodeType::Operator(name.into()));
+        for &c in children {
+            self.network.add_child(idx, c);
+        }
+        idx
+    }
+
+    pub fn behavior(&mut self, decl: BehaviorDecl, children: &[usize]) -> usize {
+        let idx = self.network.add_node(NodeType::Behavior(decl));
+        for &c in children {
+            self.network.add_child(idx, c);
+        }
+        idx
+    }
+
+    // data("name")
+    pub fn data(&mut self, name: &str) -> usize {
+        let lit = self.lit(Literal::String(name.to_string()));
+        self.op("data", &[lit])
+    }
+
+    pub fn build(mut self, root: usize) -> Network {
+        self.network.root = root;
+        self.network
+    }
+}
+
+// A fresh directory for the files of one test, removed when dropped. The process id keeps
+// concurrent test runs apart.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("comet_test_{}_{}", name, std::process::id()));
+        // Left behind by an earlier run that had the same process id and did not finish.
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assert_has_variant() {
+        let (parsed, _) = crate::parser::parse(
+            r#"Flow f { volume = data("volume")
+                divide(volume, ts_mean(volume, 20)) }"#,
+        )
+        .unwrap();
+
+        let mut b = NetworkBuilder::new();
+        let volume = b.data("volume");
+        let window = b.lit(Literal::Integer(20));
+        let mean = b.op("ts_mean", &[volume, window]);
+        let root = b.op("divide", &[volume, mean]);
+        let expected = b.build(root);
+
+        let variants = vec![parsed, expected.clone()];
+        assert_has_variant(
+            &variants,
+            "(divide (data \"volume\") (ts_mean (data \"volume\") 20))",
+        );
+        assert_eq!(variant_count(&variants), 1);
+        assert_eq!(expected.to_sexpr(), variants[0].to_sexpr());
+    }
+
+    #[test]
+    fn test_temp_dir() {
+        let dir = TempDir::new("temp_dir");
+        let path = dir.path().to_path_buf();
+        std::fs::write(path.join("a.cm"), "Flow f { data(\"close\") }").unwrap();
+        assert!(TempDir::new("temp_dir_other").path() != path);
+        drop(dir);
+        assert!(!path.exists());
+    }
+}
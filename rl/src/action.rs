@@ -32,7 +32,7 @@ impl Eq for Action {}
 impl Into<String> for &Action {
     fn into(self) -> String {
         match self {
-            Action::ShiftFloat(x) => format!("{:.8}", x),
+            Action::ShiftFloat(x) => format!("{:?}", x), // round-trips exactly
             Action::Reduce(x) => format!("!{}", x.name),
             Action::Done => format!("!done"),
             Action::ShiftInt(x) => format!("{}", x),
@@ -103,7 +103,9 @@ impl ActionSpace {
             };
             matrix[i * 3 + class_idx] = 1.0;
         }
-        Tensor::from_slice(&matrix).view([self.size() as i64, 3]).to(*device)
+        Tensor::from_slice(&matrix)
+            .view([self.size() as i64, 3])
+            .to(*device)
     }
 }
 
@@ -165,6 +167,17 @@ mod tests {
         let action_back: Action = action_str.into();
         assert_eq!(action, action_back);
 
+        // Floats keep full precision, so distinct values do not collide.
+        for x in [0.1 + 0.2, 1e-12, 123456789.123456789, 1e300] {
+            let action = Action::ShiftFloat(x);
+            let action_str: String = (&action).into();
+            let action_back: Action = action_str.into();
+            assert_eq!(action, action_back);
+        }
+        let a: String = (&Action::ShiftFloat(0.3)).into();
+        let b: String = (&Action::ShiftFloat(0.1 + 0.2)).into();
+        assert_ne!(a, b);
+
         let action = Action::ShiftString("hello".to_string());
         let action_str: String = (&action).into();
         let action_back: Action = action_str.into();
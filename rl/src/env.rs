@@ -6,8 +6,13 @@ use crate::state::AbstractMachine;
 use crate::state::SearchState;
 use parser::ast::Network;
 use runtime::runtime::Runtime;
+use std::collections::HashMap;
+use std::mem::{Discriminant, discriminant};
+use stdlib::types::Signal;
 use tch::{Device, Kind::Float, Tensor};
 
+const MAX_STACK_LEN: usize = 5;
+
 pub type Trajectory = Vec<Step>;
 pub struct Step {
     pub state_embedding: Tensor,
@@ -83,35 +88,24 @@ impl Environment {
     }
 
     pub fn get_valid_action_mask(&self, device: &Device) -> Tensor {
-        let max_stack_len = 5;
         let (stack, _callgraph) = self.state.machine.get_stack();
-        let mut valid_actions: Vec<Action> = vec![];
-        for action_idx in 0..self.action_space.size() {
-            let action: Action = self.action_space.get_action(action_idx);
-            let valid = match &action {
-                Action::Done => {
-                    stack.len() == 1 && matches!(stack[0].0, stdlib::types::Signal::DataFrame(_))
-                }
-                Action::Reduce(op_spec) => {
-                    stack.len() >= op_spec.inputs.len() && // stack size checking
-                    self.state.machine.check_reduce(&op_spec) // type checking
-                }
-                // Action::ShiftInt(_) | Action::ShiftFloat(_) | Action::ShiftString(_) => {
-                Action::ShiftInt(_) | Action::ShiftFloat(_) => {
-                    !stack.is_empty() && (stack.len() < max_stack_len)
-                }
-                Action::ShiftParam(_) | Action::ShiftString(_) => {
-                    // introducing new variable is always valid
-                    stack.len() < max_stack_len
-                }
-            };
-            if valid {
-                valid_actions.push(action);
-            }
-        }
+        let stack: Vec<Discriminant<Signal>> = stack.iter().map(|(s, _)| discriminant(s)).collect();
+        let valid_actions: Vec<Action> = (0..self.action_space.size())
+            .map(|action_idx| self.action_space.get_action(action_idx))
+            .filter(|action| is_valid_action(action, &stack))
+            .collect();
         self.action_space.calculate_mask(&valid_actions).to(*device)
     }
 
+    // Number of episodes the search can take, see count_episodes.
+    pub fn dry_run(&self) -> usize {
+        count_episodes(
+            &self.action_space,
+            &self.state.machine.param_signals(),
+            self.config.max_length,
+        )
+    }
+
     fn sample_one<T: Model>(
         &mut self,
         runtime: &mut Runtime,
@@ -266,3 +260,148 @@ impl Environment {
     //     }
     // }
 }
+
+// Whether an action may be taken with these signal types on the stack, top last.
+fn is_valid_action(action: &Action, stack: &[Discriminant<Signal>]) -> bool {
+    match action {
+        Action::Done => stack == [discriminant(&Signal::DataFrame(None))],
+        Action::Reduce(op_spec) => {
+            // stack size and type checking, as AbstractMachine::check_reduce
+            stack.len() >= op_spec.inputs.len()
+                && op_spec
+                    .inputs
+                    .iter()
+                    .zip(stack.iter().rev())
+                    .all(|(input, item)| discriminant(input) == *item)
+        }
+        Action::ShiftInt(_) | Action::ShiftFloat(_) => {
+            !stack.is_empty() && (stack.len() < MAX_STACK_LEN)
+        }
+        Action::ShiftParam(_) | Action::ShiftString(_) => {
+            // introducing new variable is always valid
+            stack.len() < MAX_STACK_LEN
+        }
+    }
+}
+
+// Counts the action sequences of at most max_length actions that end in Done, walking the same
+// validity rules as sampling but tracking only the signal types on the stack, so no callgraph
+// is built. Different sequences may build the same expression, so this bounds the number of
+// distinct expressions from above. Saturates at usize::MAX.
+pub fn count_episodes(action_space: &ActionSpace, params: &[Signal], max_length: usize) -> usize {
+    fn count(
+        actions: &[Action],
+        params: &[Discriminant<Signal>],
+        stack: &mut Vec<Discriminant<Signal>>,
+        remaining: usize,
+        memo: &mut HashMap<(Vec<Discriminant<Signal>>, usize), usize>,
+    ) -> usize {
+        if remaining == 0 {
+            return 0;
+        }
+        if let Some(&n) = memo.get(&(stack.clone(), remaining)) {
+            return n;
+        }
+        let valid: Vec<&Action> = actions
+            .iter()
+            .filter(|a| is_valid_action(a, stack))
+            .collect();
+        let mut total = 0usize;
+        for action in valid {
+            let pushed = match action {
+                Action::Done => {
+                    total = total.saturating_add(1);
+                    continue;
+                }
+                Action::ShiftInt(_) => discriminant(&Signal::Int(None)),
+                Action::ShiftFloat(_) => discriminant(&Signal::Float(None)),
+                Action::ShiftString(_) => discriminant(&Signal::String(None)),
+                Action::ShiftParam(i) => params[*i],
+                Action::Reduce(op_spec) => discriminant(&op_spec.output_shape),
+            };
+            let popped = match action {
+                Action::Reduce(op_spec) => stack.split_off(stack.len() - op_spec.inputs.len()),
+                _ => Vec::new(),
+            };
+            stack.push(pushed);
+            total = total.saturating_add(count(actions, params, stack, remaining - 1, memo));
+            stack.pop();
+            stack.extend(popped);
+        }
+        memo.insert((stack.clone(), remaining), total);
+        total
+    }
+
+    let actions: Vec<Action> = (0..action_space.size())
+        .map(|idx| action_space.get_action(idx))
+        .collect();
+    let params: Vec<Discriminant<Signal>> = params.iter().map(discriminant).collect();
+    count(
+        &actions,
+        &params,
+        &mut Vec::new(),
+        max_length,
+        &mut HashMap::new(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::SearchState;
+
+    #[test]
+    fn test_count_episodes() {
+        let (network, _) = parser::parser::parse(
+            r#"
+            Behavior Mix(a: DataFrame, b: DataFrame) {
+                operators = [rank, divide],
+                integers = [5],
+                strings = ["volume"]
+            } -> DataFrame
+            Flow f { Mix(data("close"), data("open")) }
+            "#,
+        )
+        .unwrap();
+        let (_, behavior) = network.get_behavior();
+        let action_space = ActionSpace::from(behavior);
+
+        // Takes every valid action from every state, building the callgraph as sampling does.
+        fn enumerate(state: &SearchState, action_space: &ActionSpace, remaining: usize) -> usize {
+            if remaining == 0 {
+                return 0;
+            }
+            let (stack, _) = state.machine.get_stack();
+            let stack: Vec<Discriminant<Signal>> =
+                stack.iter().map(|(s, _)| discriminant(s)).collect();
+            let mut total = 0;
+            for idx in 0..action_space.size() {
+                let action = action_space.get_action(idx);
+                if !is_valid_action(&action, &stack) {
+                    continue;
+                }
+                let mut next = state.clone();
+                next.apply_action(&action);
+                total += match action {
+                    Action::Done => 1,
+                    _ => enumerate(&next, action_space, remaining - 1),
+                };
+            }
+            total
+        }
+
+        let params = vec![Signal::DataFrame(None), Signal::DataFrame(None)];
+        let state = SearchState::new(&network);
+        for max_length in 0..7 {
+            assert_eq!(
+                count_episodes(&action_space, &params, max_length),
+                enumerate(&state, &action_space, max_length),
+                "max_length {}",
+                max_length
+            );
+        }
+        // Shifting either parameter, then Done, are the shortest episodes.
+        assert_eq!(count_episodes(&action_space, &params, 1), 0);
+        assert_eq!(count_episodes(&action_space, &params, 2), 2);
+    }
+}
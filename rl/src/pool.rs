@@ -1,9 +1,12 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-use parser::ast::Network;
+use parser::ast::{Network, NodeType, VariantTree};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use stdlib::types::Signal;
 use tch::Tensor;
 
+use crate::action::Action;
 use crate::state::AbstractMachine;
 use runtime::backtest::BasicBacktest;
 use runtime::runtime::Runtime;
@@ -11,8 +14,12 @@ use runtime::runtime::Runtime;
 static SIGNAL_LENGTH: i64 = stdlib::types::SIZE[0] as i64;
 
 pub struct Pool {
-    asts: HashMap<String, Network>,
-    returns: HashMap<String, Tensor>,
+    // Ordered by expression, so listing, saving and averaging the pool do not depend on hashing.
+    asts: BTreeMap<String, Network>,
+    canonical: HashMap<String, String>, // canonical form -> key in asts
+    collapsed: HashSet<String>,         // expressions that only reorder a pooled one
+    paths: BTreeMap<String, Vec<String>>, // expression -> actions of the episode that found it
+    returns: BTreeMap<String, Tensor>,
     portfolio_returns: Tensor,
     backtester: BasicBacktest,
     device: tch::Device,
@@ -36,10 +43,20 @@ impl Pool {
         }
     }
 
+    // Writes provenance as JSON, see provenance.
+    pub fn save_provenance(&self, path: &str) {
+        let file = std::fs::File::create(path).expect("Unable to create file");
+        write_provenance(&self.asts, &self.paths, &mut std::io::BufWriter::new(file))
+            .expect("Unable to write provenance");
+    }
+
     pub fn new(backtester: BasicBacktest, device: tch::Device, adj_coeff: f64) -> Self {
         Pool {
-            asts: HashMap::new(),
-            returns: HashMap::new(),
+            asts: BTreeMap::new(),
+            canonical: HashMap::new(),
+            collapsed: HashSet::new(),
+            paths: BTreeMap::new(),
+            returns: BTreeMap::new(),
             portfolio_returns: tch::Tensor::zeros(SIGNAL_LENGTH, (tch::Kind::Float, device)),
             backtester,
             device,
@@ -52,10 +69,39 @@ impl Pool {
         self.returns.keys().cloned().collect()
     }
 
+    pub fn simplest_exprs(&self, k: usize) -> Vec<String> {
+        simplest(&self.asts, k)
+    }
+
+    // n expressions drawn uniformly without replacement, the same ones for the same seed.
+    pub fn sampled_exprs(&self, n: usize, seed: u64) -> Vec<String> {
+        sample(self.asts.keys().cloned(), n, seed)
+    }
+
+    // All expressions, highest score first.
+    pub fn ranked_exprs(&self, score: fn(&Network) -> f64) -> Vec<String> {
+        ranked(&self.asts, score)
+    }
+
+    // The single highest scoring expression, see best.
+    pub fn best_expr(&self, score: fn(&Network) -> f64) -> Result<String, Vec<String>> {
+        best(&self.asts, score)
+    }
+
+    // The expressions in the pool with their shared prefixes merged.
+    pub fn variant_tree(&self) -> VariantTree {
+        VariantTree::from_networks(self.asts.values())
+    }
+
     pub fn len(&self) -> usize {
         self.returns.len()
     }
 
+    // Number of expressions not added because the pool has them with operands reordered.
+    pub fn collapsed(&self) -> usize {
+        self.collapsed.len()
+    }
+
     pub fn stats(&self) -> HashMap<String, (f64, f64, f64, f64)> {
         // This function is slow. Debug / Analysis use only.
         let mut stats: HashMap<String, (f64, f64, f64, f64)> = HashMap::new();
@@ -86,14 +132,25 @@ impl Pool {
         }
     }
 
-    pub fn insert(&mut self, runtime: &mut Runtime, sub_ast: Network) {
+    // path is the action sequence that built sub_ast, kept as its provenance.
+    pub fn insert(&mut self, runtime: &mut Runtime, sub_ast: Network, path: &[Action]) {
         // you can use Network::extract_subtree to get subtrees
         let hash_str: String = sub_ast.format_node(sub_ast.root);
+        let canonical = sub_ast.canonical_form(sub_ast.root);
+        if let Some(existing) = self.canonical.get(&canonical) {
+            if *existing != hash_str {
+                self.collapsed.insert(hash_str);
+            }
+            return;
+        }
         if !self.asts.contains_key(&hash_str) {
+            self.canonical.insert(canonical, hash_str.clone());
             println!("Inserting new equation to the pool : {}", hash_str);
             let pos = runtime.lookup_or_run(&sub_ast, sub_ast.root);
             let returns = self.backtester.calc_returns(&pos.to_dataframe(self.device));
             self.asts.insert(hash_str.clone(), sub_ast);
+            self.paths
+                .insert(hash_str.clone(), path.iter().map(Into::into).collect());
             self.returns.insert(hash_str, returns);
             self.calc_portfolio_returns();
         }
@@ -250,6 +307,166 @@ impl Pool {
     }
 }
 
+// The k simplest expressions by node count. Ties are broken by the expression string.
+pub fn simplest(asts: &BTreeMap<String, Network>, k: usize) -> Vec<String> {
+    let mut ranked: Vec<(usize, &String)> = asts
+        .iter()
+        .map(|(expr, ast)| (ast.complexity(ast.root), expr))
+        .collect();
+    ranked.sort();
+    ranked.into_iter().take(k).map(|(_, e)| e.clone()).collect()
+}
+
+// A uniform sample of n items, in one pass over the iterator without collecting it (reservoir
+// sampling). The sample is in no particular order. Fewer than n items are all returned.
+pub fn sample<T>(items: impl IntoIterator<Item = T>, n: usize, seed: u64) -> Vec<T> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut reservoir = Vec::with_capacity(n);
+    for (i, item) in items.into_iter().enumerate() {
+        if i < n {
+            reservoir.push(item);
+        } else {
+            let j = rng.gen_range(0..=i);
+            if j < n {
+                reservoir[j] = item;
+            }
+        }
+    }
+    reservoir
+}
+
+pub fn ranked(asts: &BTreeMap<String, Network>, score: fn(&Network) -> f64) -> Vec<String> {
+    let mut scored: Vec<(f64, &String)> =
+        asts.iter().map(|(expr, ast)| (score(ast), expr)).collect();
+    // Ties are broken by the expression.
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    scored.into_iter().map(|(_, e)| e.clone()).collect()
+}
+
+// The expression with the highest score, preferring the simpler one among equal scores.
+// When several expressions are equally good on both counts there is no single answer,
+// and all of them are returned as the error. An empty pool returns an empty error.
+pub fn best(
+    asts: &BTreeMap<String, Network>,
+    score: fn(&Network) -> f64,
+) -> Result<String, Vec<String>> {
+    let key = |ast: &Network| (score(ast), std::cmp::Reverse(ast.complexity(ast.root)));
+    let top = asts
+        .values()
+        .map(key)
+        .max_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    let tied: Vec<String> = asts
+        .iter()
+        .filter(|(_, ast)| {
+            let k = key(ast);
+            top.is_some_and(|t| k.0.total_cmp(&t.0).is_eq() && k.1 == t.1)
+        })
+        .map(|(expr, _)| expr.clone())
+        .collect();
+    match &tied[..] {
+        [only] => Ok(only.clone()),
+        _ => Err(tied),
+    }
+}
+
+// For each pooled expression, keyed by the expression itself: the actions of the episode that
+// first produced it and the operators it uses, in pre-order without repeats.
+// [{ "id": "rank(data(\"close\"))", "path": ["\"close\"", "!data", "!rank", "!done"],
+//    "operators": ["rank", "data"] }]
+pub fn provenance(
+    asts: &BTreeMap<String, Network>,
+    paths: &BTreeMap<String, Vec<String>>,
+) -> serde_json::Value {
+    let variants = asts
+        .iter()
+        .map(|(expr, ast)| variant_provenance(expr, ast, paths))
+        .collect();
+    serde_json::Value::Array(variants)
+}
+
+// Same JSON as provenance, written one variant at a time so that a large pool is never
+// held in memory as a whole document.
+pub fn write_provenance(
+    asts: &BTreeMap<String, Network>,
+    paths: &BTreeMap<String, Vec<String>>,
+    out: &mut impl std::io::Write,
+) -> std::io::Result<()> {
+    out.write_all(b"[")?;
+    for (i, (expr, ast)) in asts.iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        out.write_all(b"\n")?;
+        serde_json::to_writer_pretty(&mut *out, &variant_provenance(expr, ast, paths))?;
+    }
+    out.write_all(b"\n]\n")?;
+    out.flush()
+}
+
+fn variant_provenance(
+    expr: &str,
+    ast: &Network,
+    paths: &BTreeMap<String, Vec<String>>,
+) -> serde_json::Value {
+    let mut operators: Vec<&str> = Vec::new();
+    for token in ast.prefix_tokens() {
+        if let Some(op) = stdlib::OperatorSpec::lookup(&token) {
+            if !operators.contains(&op.name) {
+                operators.push(op.name);
+            }
+        }
+    }
+    serde_json::json!({
+        "id": expr,
+        "path": paths.get(expr).cloned().unwrap_or_default(),
+        "operators": operators,
+    })
+}
+
+const NORMALIZERS: [&str; 4] = ["rank", "cs_zscore", "scale", "sigmoid"];
+
+// Structural stand-in for the information ratio, computed without running the expression.
+// Cross-sectional normalization and neutralization make a signal comparable across assets,
+// so expressions using them rank above raw sources and arithmetic on raw sources.
+// Larger expressions lose a little, so that parsimony breaks ties.
+pub fn ir_proxy(ast: &Network) -> f64 {
+    let reachable = {
+        let mut seen = Vec::new();
+        let mut stack = vec![ast.root];
+        while let Some(idx) = stack.pop() {
+            if idx < ast.nodes.len() && !seen.contains(&idx) {
+                seen.push(idx);
+                stack.extend(&ast.nodes[idx].children);
+            }
+        }
+        seen
+    };
+    let op_name = |idx: usize| match &ast.nodes[idx].node_type {
+        NodeType::Operator(op) => Some(op.name),
+        _ => None,
+    };
+    let uses = |names: &[&str]| {
+        reachable
+            .iter()
+            .any(|&idx| op_name(idx).is_some_and(|n| names.contains(&n)))
+    };
+
+    let mut score = 0.0;
+    if uses(&["neutralize"]) {
+        score += 2.0;
+    }
+    if uses(&NORMALIZERS) {
+        score += 1.0;
+    }
+    // The root is visited first.
+    match reachable.first().and_then(|&idx| op_name(idx)) {
+        Some(name) if name == "neutralize" || NORMALIZERS.contains(&name) => score += 1.0,
+        Some("data") => score -= 1.0,
+        _ => {}
+    }
+    score - 0.01 * ast.complexity(ast.root) as f64
+}
+
 impl Pool {
     pub fn calc_potential(
         &self,
@@ -351,3 +568,207 @@ impl Pool {
         // Ok((potential, reward))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simplest() {
+        let srcs = [
+            r#"Flow f { divide(data("volume"), ts_mean(data("volume"), 20)) }"#,
+            r#"Flow f { data("volume") }"#,
+            r#"Flow f { ts_mean(data("volume"), 20) }"#,
+            r#"Flow f { data("adv20") }"#,
+            r#"Flow f { flip(data("adv20")) }"#,
+        ];
+        let asts: BTreeMap<String, Network> = srcs
+            .iter()
+            .map(|src| {
+                let (network, _) = parser::parser::parse(src).unwrap();
+                (network.format_node(network.root), network)
+            })
+            .collect();
+        assert_eq!(
+            simplest(&asts, 3),
+            vec![
+                "data(\"adv20\")".to_string(),
+                "data(\"volume\")".to_string(),
+                "flip(data(\"adv20\"))".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_insertion_order() {
+        let srcs = [
+            r#"Flow f { rank(data("volume")) }"#,
+            r#"Flow f { data("close") }"#,
+            r#"Flow f { ts_mean(data("volume"), 20) }"#,
+            r#"Flow f { rank(data("close")) }"#,
+        ];
+        let pool = |order: &[usize]| -> BTreeMap<String, Network> {
+            let mut asts = BTreeMap::new();
+            for &i in order {
+                let (network, _) = parser::parser::parse(srcs[i]).unwrap();
+                asts.insert(network.format_node(network.root), network);
+            }
+            asts
+        };
+        let (a, b) = (pool(&[0, 1, 2, 3]), pool(&[3, 2, 1, 0]));
+        assert!(a.keys().eq(b.keys()));
+        assert_eq!(simplest(&a, 2), simplest(&b, 2));
+        assert_eq!(ranked(&a, ir_proxy), ranked(&b, ir_proxy));
+        let paths = BTreeMap::new();
+        assert_eq!(provenance(&a, &paths), provenance(&b, &paths));
+    }
+
+    #[test]
+    fn test_collapsed() {
+        let frame = || {
+            Tensor::zeros(
+                stdlib::types::SIZE.as_slice(),
+                (tch::Kind::Float, tch::Device::Cpu),
+            )
+        };
+        let sources = HashMap::from([
+            ("returns_d1".to_string(), frame()),
+            ("close".to_string(), frame()),
+            ("volume".to_string(), frame()),
+        ]);
+        let mut runtime = Runtime::with_test_sources(100, sources);
+        let backtester = BasicBacktest::new(&mut runtime.dmgr, "returns_d1");
+        let mut pool = Pool::new(backtester, tch::Device::Cpu, 1.0);
+        let mut insert = |src: &str| {
+            let (network, _) = parser::parser::parse(src).unwrap();
+            pool.insert(&mut runtime, network, &[]);
+        };
+        insert(r#"Flow f { add(data("close"), data("volume")) }"#);
+        // A reordering found twice is one collapsed expression.
+        insert(r#"Flow f { add(data("volume"), data("close")) }"#);
+        insert(r#"Flow f { add(data("volume"), data("close")) }"#);
+        assert_eq!((pool.len(), pool.collapsed()), (1, 1));
+    }
+
+    #[test]
+    fn test_sample() {
+        let a = sample(0..1000, 10, 7);
+        assert_eq!(a.len(), 10);
+        assert_eq!(a, sample(0..1000, 10, 7));
+        assert_ne!(a, sample(0..1000, 10, 8));
+        let mut distinct = a.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), 10);
+
+        // Every item is kept when there are no more than asked for.
+        assert_eq!(sample(0..3, 10, 7), vec![0, 1, 2]);
+        assert_eq!(sample(0..3, 0, 7), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn test_ir_proxy() {
+        let score = |src: &str| ir_proxy(&parser::parser::parse(src).unwrap().0);
+        let raw = score(r#"Flow f { divide(data("volume"), data("adv20")) }"#);
+        let normalized = score(r#"Flow f { rank(divide(data("volume"), data("adv20"))) }"#);
+        let neutralized = score(
+            r#"Flow f { neutralize(rank(divide(data("volume"), data("adv20"))), data("sector")) }"#,
+        );
+        assert!(neutralized > normalized);
+        assert!(normalized > raw);
+        assert!(raw > score(r#"Flow f { data("volume") }"#));
+
+        let asts: BTreeMap<String, Network> = [
+            r#"Flow f { data("volume") }"#,
+            r#"Flow f { neutralize(rank(data("volume")), data("sector")) }"#,
+            r#"Flow f { rank(data("volume")) }"#,
+        ]
+        .iter()
+        .map(|src| {
+            let (network, _) = parser::parser::parse(src).unwrap();
+            (network.format_node(network.root), network)
+        })
+        .collect();
+        assert_eq!(
+            ranked(&asts, ir_proxy),
+            vec![
+                "neutralize(rank(data(\"volume\")), data(\"sector\"))".to_string(),
+                "rank(data(\"volume\"))".to_string(),
+                "data(\"volume\")".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_best() {
+        let pool = |srcs: &[&str]| -> BTreeMap<String, Network> {
+            srcs.iter()
+                .map(|src| {
+                    let (network, _) = parser::parser::parse(src).unwrap();
+                    (network.format_node(network.root), network)
+                })
+                .collect()
+        };
+        let asts = pool(&[
+            r#"Flow f { data("volume") }"#,
+            r#"Flow f { rank(data("volume")) }"#,
+            r#"Flow f { rank(flip(data("volume"))) }"#,
+        ]);
+        assert_eq!(
+            best(&asts, ir_proxy),
+            Ok("rank(data(\"volume\"))".to_string())
+        );
+
+        // Same score and size: there is no single most specific expression.
+        let asts = pool(&[
+            r#"Flow f { rank(data("volume")) }"#,
+            r#"Flow f { rank(data("close")) }"#,
+        ]);
+        assert_eq!(
+            best(&asts, ir_proxy),
+            Err(vec![
+                "rank(data(\"close\"))".to_string(),
+                "rank(data(\"volume\"))".to_string(),
+            ])
+        );
+        assert_eq!(best(&BTreeMap::new(), ir_proxy), Err(vec![]));
+    }
+
+    #[test]
+    fn test_provenance() {
+        let (network, _) =
+            parser::parser::parse(r#"Flow f { rank(ts_mean(data("close"), 5)) }"#).unwrap();
+        let expr = network.format_node(network.root);
+        let asts: BTreeMap<String, Network> = [(expr.clone(), network)].into();
+        let path: Vec<String> = ["\"close\"", "!data", "5", "!ts_mean", "!rank", "!done"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let paths: BTreeMap<String, Vec<String>> = [(expr.clone(), path.clone())].into();
+
+        let json = provenance(&asts, &paths);
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "id": expr,
+                "path": path,
+                "operators": ["rank", "ts_mean", "data"],
+            }])
+        );
+        let mut written = Vec::new();
+        write_provenance(&asts, &paths, &mut written).unwrap();
+        let parsed: serde_json::Value = serde_json::from_slice(&written).unwrap();
+        assert_eq!(parsed, json);
+        let mut empty = Vec::new();
+        write_provenance(&BTreeMap::new(), &paths, &mut empty).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&empty).unwrap(),
+            serde_json::json!([])
+        );
+
+        // Recorded actions parse back into the same actions.
+        let actions: Vec<Action> = path.iter().cloned().map(Action::from).collect();
+        let round_trip: Vec<String> = actions.iter().map(Into::into).collect();
+        assert_eq!(round_trip, path);
+    }
+}
@@ -27,6 +27,11 @@ impl AbstractMachine {
         (&self.stack, &self.callgraph)
     }
 
+    // Types of the behavior's parameters, in ShiftParam order.
+    pub fn param_signals(&self) -> Vec<Signal> {
+        self.params.iter().map(|(s, _)| s.clone()).collect()
+    }
+
     pub fn check_reduce(&self, operator_spec: &OperatorSpec) -> bool {
         // type checking
         // assert!(operator_spec.inputs.len() <= self.stack.len());
@@ -32,6 +32,32 @@ impl DataManager {
         dmgr
     }
 
+    // A data manager over in-memory sources only, e.g. a synthetic universe for tests.
+    // Every source must have the same (rows, columns) shape.
+    pub fn from_sources(sources: HashMap<String, Tensor>, device: Option<Device>) -> Self {
+        let dev = device.unwrap_or(Device::Cpu);
+        let mut data_size = (0, 0);
+        for (name, data) in &sources {
+            let size = (data.size()[0] as usize, data.size()[1] as usize);
+            if data_size != (0, 0) && size != data_size {
+                panic!(
+                    "Data shape mismatch for {}: expected {:?}, got {:?}",
+                    name, data_size, size
+                );
+            }
+            data_size = size;
+        }
+        DataManager {
+            cache: sources
+                .into_iter()
+                .map(|(name, data)| (name, data.to(dev)))
+                .collect(),
+            data_dir: PathBuf::new(),
+            data_size,
+            device: dev,
+        }
+    }
+
     pub fn get_data(&mut self, name: &str) -> Option<Tensor> {
         if let Some(data) = self.cache.get(name) {
             return Some(data.shallow_clone());
@@ -2,6 +2,7 @@ use crate::dmgr::DataManager;
 use lru::LruCache;
 use parser::ast::{Network, Node, NodeType};
 use parser::expr::Literal;
+use std::collections::HashMap;
 use std::num::NonZeroUsize;
 use std::path::PathBuf;
 use stdlib::types::Signal;
@@ -29,8 +30,20 @@ impl Runtime {
         }
     }
 
+    // A runtime whose data("name") reads only the given sources, with no data directory.
+    pub fn with_test_sources(capacity: usize, sources: HashMap<String, tch::Tensor>) -> Self {
+        Runtime {
+            dmgr: DataManager::from_sources(sources, None),
+            expr_cache: LruCache::new(NonZeroUsize::new(capacity).unwrap()),
+            expr_lookups: 0,
+            expr_hits: 0,
+            enable: true,
+        }
+    }
+
     pub fn lookup_or_run(&mut self, callgraph: &Network, root: usize) -> &Signal {
-        let hash_key: String = callgraph.format_node(root);
+        // add(a, b) and add(b, a) compute the same signal, so they share one entry.
+        let hash_key: String = callgraph.canonical_form(root);
 
         //TODO: mask outside universe to nan. see universe.csv.gz
         if self.expr_cache.get(&hash_key).is_none() {
@@ -73,7 +86,9 @@ impl Runtime {
             NodeType::Literal(Literal::Boolean(_literal)) => {
                 panic!("Boolean literal not supported")
             }
-            NodeType::Literal(Literal::Integer(literal)) => Signal::Int(Some(literal.clone())),
+            NodeType::Literal(Literal::Integer(literal) | Literal::Days(literal)) => {
+                Signal::Int(Some(literal.clone()))
+            }
             NodeType::Literal(Literal::Float(literal)) => Signal::Float(Some(literal.clone())),
             NodeType::Literal(Literal::String(literal)) => Signal::String(Some(literal.clone())),
         }
@@ -175,3 +190,39 @@ impl Runtime {
 //         let _result = runtime.run(&program);
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tch::Tensor;
+
+    #[test]
+    fn test_runtime_test_sources() {
+        // Two sources over 3 days and 4 instruments.
+        let close = Tensor::from_slice(&[
+            10.0, 20.0, 30.0, 40.0, 11.0, 19.0, 33.0, 38.0, 12.0, 21.0, 29.0, 41.0,
+        ])
+        .view((3, 4));
+        let volume = Tensor::from_slice(&[
+            100.0, 150.0, 90.0, 400.0, 120.0, 140.0, 99.0, 380.0, 80.0, 160.0, 87.0, 420.0,
+        ])
+        .view((3, 4));
+        let sources = HashMap::from([("close".to_string(), close), ("volume".to_string(), volume)]);
+        let mut runtime = Runtime::with_test_sources(100, sources);
+        assert_eq!(runtime.dmgr.data_size, (3, 4));
+
+        let (network, _) =
+            parser::parser::parse(r#"Flow f { cs_zscore(divide(data("volume"), data("close"))) }"#)
+                .unwrap();
+        let Signal::DataFrame(Some(df)) = runtime.lookup_or_run(&network, network.root) else {
+            panic!("Expected a DataFrame");
+        };
+        assert_eq!(df.size(), vec![3, 4]);
+        // A cross-sectional z-score is centered on every day.
+        let day_sums: Vec<f64> = df
+            .sum_dim_intlist(Some(&[1][..]), false, df.kind())
+            .try_into()
+            .unwrap();
+        assert!(day_sums.iter().all(|s| s.abs() < 1e-9));
+    }
+}
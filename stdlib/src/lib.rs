@@ -7,7 +7,10 @@ mod op_data;
 mod op_divide;
 mod op_flip;
 mod op_multiply;
+mod op_neutralize;
 mod op_pow;
+mod op_quantile;
+mod op_scale;
 mod op_subtract;
 mod op_time_series;
 mod op_ts_mean;
@@ -22,8 +25,10 @@ mod op_ts_stddev;
 mod op_ts_rank;
 mod op_ts_cov;
 mod op_ts_corr;
+mod op_winsorize;
 
 pub mod types;
+use std::sync::RwLock;
 use types::Signal;
 
 pub struct OperatorSpec {
@@ -47,98 +52,147 @@ impl PartialEq for OperatorSpec {
 
 impl From<&str> for &OperatorSpec {
     fn from(sig_name: &str) -> Self {
-        match sig_name {
-            "data" => &op_data::OP_DATA,
-            "add" => &op_add::OP_ADD,
-            "subtract" => &op_subtract::OP_SUBTRACT,
-            "divide" => &op_divide::OP_DIVIDE,
-            "multiply" => &op_multiply::OP_MULTIPLY,
-            "flip" => &op_flip::OP_FLIP,
-            "pow" => &op_pow::OP_POW,
-            // "cs_rank" => &op_cs_rank::OP_CS_RANK, same with rank()
-            "cs_zscore" => &op_cs_zscore::OP_CS_ZSCORE,
-            "ts_mean" => &op_ts_mean::OP_TS_MEAN,
-            "rank" => &op_cross_section::OP_RANK,
-            "rank_add" => &op_cross_section::OP_RANK_ADD,
-            "rank_sub" => &op_cross_section::OP_RANK_SUB,
-            "rank_mul" => &op_cross_section::OP_RANK_MUL,
-            "rank_div" => &op_cross_section::OP_RANK_DIV,
-            "sign" => &op_cross_section::OP_SIGN,
-            "sigmoid" => &op_cross_section::OP_SIGMOID,
-            "delay" => &op_time_series::OP_DELAY,
-            "delta" => &op_time_series::OP_DELTA,
-            "ts_return" => &op_time_series::OP_TS_RETURN,
-            "ts_max" => &op_ts_max::OP_TS_MAX,
-            "ts_min" => &op_ts_min::OP_TS_MIN,
-            "ts_argmax" => &op_ts_argmax::OP_TS_ARGMAX,
-            "ts_argmin" => &op_ts_argmin::OP_TS_ARGMIN,
-            "ts_sum" => &op_ts_sum::OP_TS_SUM,
-            "ts_prod" => &op_ts_prod::OP_TS_PROD,
-            "ts_nanmean" => &op_ts_nanmean::OP_TS_NANMEAN,
-            "ts_stddev" => &op_ts_stddev::OP_TS_STDDEV,
-            "ts_rank" => &op_ts_rank::OP_TS_RANK,
-            "ts_cov" => &op_ts_cov::OP_TS_COV,
-            "ts_corr" => &op_ts_corr::OP_TS_CORR,
-            // "ts_diff" => OperatorSpec {
-            //     name: "ts_diff",
-            //     inputs: &[Signal::Int(None), Signal::DataFrame(None)],
-            //     output_shape: Signal::DataFrame(None),
-            //     execute: |args| match (&args[0], &args[1]) {
-            //         (Signal::Int(Some(period)), Signal::DataFrame(Some(a))) => {
-            //             let p = *period as i64;
-            //             let shifted = a.roll(&[p], &[0]);
-            //             if p > 0 && p <= a.size()[0] {
-            //                 let mut slice = shifted.narrow(0, 0, p);
-            //                 let nan = tch::Tensor::full(&[1], f64::NAN, (a.kind(), a.device()));
-            //                 let _ = slice.copy_(&nan);
-            //             }
-            //             Signal::DataFrame(Some(a - shifted))
-            //         }
-            //         _ => panic!("ts_diff expected Int and DataFrame"),
-            //     },
-            // },
-            // "ts_mean" => OperatorSpec {
-            //     name: "ts_mean",
-            //     inputs: &[Signal::DataFrame(None), Signal::Int(None)],
-            //     output_shape: Signal::DataFrame(None),
-            //     execute: |args| {
-            //         match (&args[0], &args[1]) {
-            //             (Signal::DataFrame(Some(a)), Signal::Int(Some(_period))) => {
-            //                 // Dummy implementation for now to pass tests (returns same DF)
-            //                 // TODO: implement rolling window mean with unfold/conv1d
-            //                 Signal::DataFrame(Some(a.shallow_clone()))
-            //             }
-            //             _ => panic!("ts_mean expected DataFrame and Int"),
-            //         }
-            //     },
-            // },
-            // "ts_rank" => OperatorSpec {
-            //     name: "ts_rank",
-            //     inputs: &[Signal::DataFrame(None), Signal::Int(None)],
-            //     output_shape: Signal::DataFrame(None),
-            //     execute: |args| {
-            //         match (&args[0], &args[1]) {
-            //             (Signal::DataFrame(Some(a)), Signal::Int(Some(_period))) => {
-            //                 // Dummy implementation for now to pass tests (returns same DF)
-            //                 // TODO: implement rolling window rank
-            //                 Signal::DataFrame(Some(a.shallow_clone()))
-            //             }
-            //             _ => panic!("ts_rank expected DataFrame and Int"),
-            //         }
-            //     },
-            // },
-            // "consume_float" => OperatorSpec {
-            //     name: "consume_float",
-            //     inputs: &[Signal::Float(None)],
-            //     output_shape: Signal::Void,
-            //     execute: |_args| Signal::Void,
-            // },
-            _ => panic!("Could not find {} in the stdlib", sig_name),
-        }
+        OperatorSpec::lookup(sig_name)
+            .unwrap_or_else(|| panic!("Could not find {} in the stdlib", sig_name))
     }
 }
 
+// The built-in operators, found by OperatorSpec::lookup and listed by OperatorSpec::all in
+// this order.
+static OPERATORS: &[&OperatorSpec] = &[
+    &op_data::OP_DATA,
+    &op_add::OP_ADD,
+    &op_subtract::OP_SUBTRACT,
+    &op_divide::OP_DIVIDE,
+    &op_multiply::OP_MULTIPLY,
+    &op_flip::OP_FLIP,
+    &op_pow::OP_POW,
+    // &op_cs_rank::OP_CS_RANK, same with rank()
+    &op_cs_zscore::OP_CS_ZSCORE,
+    &op_ts_mean::OP_TS_MEAN,
+    &op_cross_section::OP_RANK,
+    &op_cross_section::OP_RANK_ADD,
+    &op_cross_section::OP_RANK_SUB,
+    &op_cross_section::OP_RANK_MUL,
+    &op_cross_section::OP_RANK_DIV,
+    &op_cross_section::OP_SIGN,
+    &op_cross_section::OP_SIGMOID,
+    &op_neutralize::OP_NEUTRALIZE,
+    &op_scale::OP_SCALE,
+    &op_quantile::OP_QUANTILE,
+    &op_winsorize::OP_WINSORIZE,
+    &op_time_series::OP_DELAY,
+    &op_time_series::OP_DELTA,
+    &op_time_series::OP_TS_RETURN,
+    &op_ts_max::OP_TS_MAX,
+    &op_ts_min::OP_TS_MIN,
+    &op_ts_argmax::OP_TS_ARGMAX,
+    &op_ts_argmin::OP_TS_ARGMIN,
+    &op_ts_sum::OP_TS_SUM,
+    &op_ts_prod::OP_TS_PROD,
+    &op_ts_nanmean::OP_TS_NANMEAN,
+    &op_ts_stddev::OP_TS_STDDEV,
+    &op_ts_rank::OP_TS_RANK,
+    &op_ts_cov::OP_TS_COV,
+    &op_ts_corr::OP_TS_CORR,
+    // "ts_diff" => OperatorSpec {
+    //     name: "ts_diff",
+    //     inputs: &[Signal::Int(None), Signal::DataFrame(None)],
+    //     output_shape: Signal::DataFrame(None),
+    //     execute: |args| match (&args[0], &args[1]) {
+    //         (Signal::Int(Some(period)), Signal::DataFrame(Some(a))) => {
+    //             let p = *period as i64;
+    //             let shifted = a.roll(&[p], &[0]);
+    //             if p > 0 && p <= a.size()[0] {
+    //                 let mut slice = shifted.narrow(0, 0, p);
+    //                 let nan = tch::Tensor::full(&[1], f64::NAN, (a.kind(), a.device()));
+    //                 let _ = slice.copy_(&nan);
+    //             }
+    //             Signal::DataFrame(Some(a - shifted))
+    //         }
+    //         _ => panic!("ts_diff expected Int and DataFrame"),
+    //     },
+    // },
+    // "ts_mean" => OperatorSpec {
+    //     name: "ts_mean",
+    //     inputs: &[Signal::DataFrame(None), Signal::Int(None)],
+    //     output_shape: Signal::DataFrame(None),
+    //     execute: |args| {
+    //         match (&args[0], &args[1]) {
+    //             (Signal::DataFrame(Some(a)), Signal::Int(Some(_period))) => {
+    //                 // Dummy implementation for now to pass tests (returns same DF)
+    //                 // TODO: implement rolling window mean with unfold/conv1d
+    //                 Signal::DataFrame(Some(a.shallow_clone()))
+    //             }
+    //             _ => panic!("ts_mean expected DataFrame and Int"),
+    //         }
+    //     },
+    // },
+    // "ts_rank" => OperatorSpec {
+    //     name: "ts_rank",
+    //     inputs: &[Signal::DataFrame(None), Signal::Int(None)],
+    //     output_shape: Signal::DataFrame(None),
+    //     execute: |args| {
+    //         match (&args[0], &args[1]) {
+    //             (Signal::DataFrame(Some(a)), Signal::Int(Some(_period))) => {
+    //                 // Dummy implementation for now to pass tests (returns same DF)
+    //                 // TODO: implement rolling window rank
+    //                 Signal::DataFrame(Some(a.shallow_clone()))
+    //             }
+    //             _ => panic!("ts_rank expected DataFrame and Int"),
+    //         }
+    //     },
+    // },
+    // "consume_float" => OperatorSpec {
+    //     name: "consume_float",
+    //     inputs: &[Signal::Float(None)],
+    //     output_shape: Signal::Void,
+    //     execute: |_args| Signal::Void,
+    // },
+];
+
 impl OperatorSpec {
+    // The built-in operators, then the registered ones in order of registration.
+    pub fn all() -> Vec<&'static OperatorSpec> {
+        let mut specs = OPERATORS.to_vec();
+        specs.extend(REGISTERED.read().unwrap().iter().copied());
+        specs
+    }
+
+    // None when the stdlib has no operator with this name.
+    pub fn lookup(sig_name: &str) -> Option<&'static OperatorSpec> {
+        if let Some(spec) = OPERATORS.iter().copied().find(|s| s.name == sig_name) {
+            return Some(spec);
+        }
+        REGISTERED
+            .read()
+            .unwrap()
+            .iter()
+            .copied()
+            .find(|s| s.name == sig_name)
+    }
+
+    // Adds an operator defined outside the stdlib, e.g. by a plugin crate, so that flows,
+    // behaviors and the runtime can call it by name. The contract is that of the built-in ones:
+    // - `inputs` lists the argument types in call order. `execute` is only called after the
+    //   arguments have been checked against them, so it may panic on other signals.
+    // - `execute` returns a signal of the type of `output_shape`. A DataFrame output keeps the
+    //   shape of the DataFrame inputs and must not look ahead in time.
+    // Names must be unique, built-in names included. Registered operators live for the process.
+    pub fn register(spec: &'static OperatorSpec) -> Result<(), String> {
+        // Checked under the write lock, so two threads cannot register the same name.
+        let mut registered = REGISTERED.write().unwrap();
+        let taken = OPERATORS
+            .iter()
+            .chain(registered.iter())
+            .any(|s| s.name == spec.name);
+        if taken {
+            return Err(format!("Operator {} is already defined", spec.name));
+        }
+        registered.push(spec);
+        Ok(())
+    }
+
     pub fn execute(&self, args: &[Signal]) -> Result<Signal, String> {
         let arity = self.inputs.len();
         if args.len() < arity {
@@ -158,6 +212,8 @@ impl OperatorSpec {
     }
 }
 
+static REGISTERED: RwLock<Vec<&'static OperatorSpec>> = RwLock::new(Vec::new());
+
 // TODO:
 // 4. numerical accuracy neeed to be checked.
 
@@ -187,39 +243,16 @@ mod tests {
     }
 
     fn get_all_ops() -> Vec<&'static str> {
-        vec![
-            "data",
-            "add",
-            "subtract",
-            "divide",
-            "multiply",
-            "flip",
-            "pow",
-            // "cs_rank",
-            "cs_zscore",
-            "ts_mean",
-            "rank",
-            "rank_add",
-            "rank_sub",
-            "rank_mul",
-            "rank_div",
-            "sign",
-            "sigmoid",
-            "delay",
-            "delta",
-            "ts_return",
-            "ts_max",
-            "ts_min",
-            "ts_argmax",
-            "ts_argmin",
-            "ts_sum",
-            "ts_prod",
-            "ts_nanmean",
-            "ts_stddev",
-            "ts_rank",
-            "ts_cov",
-            "ts_corr",
-        ]
+        OPERATORS.iter().map(|s| s.name).collect()
+    }
+
+    #[test]
+    fn test_all_ops() {
+        let names: Vec<&str> = OperatorSpec::all().iter().map(|s| s.name).collect();
+        assert_eq!(names[..OPERATORS.len()], get_all_ops());
+        for name in get_all_ops() {
+            assert_eq!(OperatorSpec::lookup(name).map(|s| s.name), Some(name));
+        }
     }
 
     #[test]
@@ -0,0 +1,58 @@
+use crate::{OperatorSpec, types::Signal};
+
+pub static OP_NEUTRALIZE: OperatorSpec = OperatorSpec {
+    name: "neutralize",
+    inputs: &[Signal::DataFrame(None), Signal::DataFrame(None)],
+    output_shape: Signal::DataFrame(None),
+    execute: |args| match (&args[0], &args[1]) {
+        (Signal::DataFrame(Some(a)), Signal::DataFrame(Some(group))) => {
+            // Demean the signal within each group, cross-sectionally (along dim 1).
+            // The group dataframe holds categorical labels (e.g. sector codes).
+            let valid = a
+                .isnan()
+                .logical_not()
+                .logical_and(&group.isnan().logical_not());
+            let valid_f = valid.to_kind(a.kind());
+            let (labels, inverse) = group.nan_to_num(0.0, 0.0, 0.0)._unique(true, true);
+
+            let mut shape = a.size();
+            shape[1] = labels.size()[0];
+            let values = a.nan_to_num(0.0, 0.0, 0.0) * &valid_f;
+            let sums = tch::Tensor::zeros(shape.as_slice(), (a.kind(), a.device()))
+                .scatter_add(1, &inverse, &values);
+            let counts = tch::Tensor::zeros(shape.as_slice(), (a.kind(), a.device()))
+                .scatter_add(1, &inverse, &valid_f);
+            let mean = (sums / counts.clamp_min(1.0)).gather(1, &inverse, false);
+
+            let nan = tch::Tensor::full(a.size().as_slice(), f64::NAN, (a.kind(), a.device()));
+            Signal::DataFrame(Some((a - mean).where_self(&valid, &nan)))
+        }
+        _ => panic!("neutralize expected two DataFrames"),
+    },
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tch::Tensor;
+
+    #[test]
+    fn test_neutralize() {
+        assert_eq!(OP_NEUTRALIZE.inputs.len(), 2);
+
+        // Two groups per row: columns 0,1 are group 1 and columns 2,3 are group 2.
+        let a = Tensor::from_slice(&[1.0, 3.0, 10.0, 20.0, 2.0, f64::NAN, 4.0, 8.0]).view([2, 4]);
+        let g = Tensor::from_slice(&[1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 2.0, 2.0]).view([2, 4]);
+        let out =
+            (OP_NEUTRALIZE.execute)(&[Signal::DataFrame(Some(a)), Signal::DataFrame(Some(g))]);
+        if let Signal::DataFrame(Some(res)) = out {
+            let expected =
+                Tensor::from_slice(&[-1.0, 1.0, -5.0, 5.0, 0.0, f64::NAN, -2.0, 2.0]).view([2, 4]);
+            let is_all_true =
+                i64::try_from(res.isclose(&expected, 1e-5, 1e-8, true).all()).unwrap() != 0;
+            assert!(is_all_true);
+        } else {
+            panic!("Wrong output");
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::{OperatorSpec, types::Signal};
+
+pub static OP_QUANTILE: OperatorSpec = OperatorSpec {
+    name: "quantile",
+    inputs: &[Signal::DataFrame(None), Signal::Float(None)],
+    output_shape: Signal::DataFrame(None),
+    execute: |args| match (&args[0], &args[1]) {
+        (Signal::DataFrame(Some(a)), Signal::Float(Some(q))) => {
+            // A behavior can pick q from its floats, which are not limited to [0, 1].
+            if !(0.0..=1.0).contains(q) {
+                let nan = tch::Tensor::full(a.size().as_slice(), f64::NAN, (a.kind(), a.device()));
+                return Signal::DataFrame(Some(nan));
+            }
+            // Cross-sectional threshold of each row, ignoring NaN, broadcast over the row.
+            // Compare against it to select e.g. the top decile with q = 0.9.
+            let threshold = a.nanquantile_scalar(*q, Some(1), true, "linear");
+            Signal::DataFrame(Some(threshold.expand_as(a).contiguous()))
+        }
+        _ => panic!("quantile expected DataFrame and Float"),
+    },
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tch::Tensor;
+
+    #[test]
+    fn test_quantile() {
+        let a = Tensor::from_slice(&[1.0, 2.0, 3.0, 5.0, f64::NAN, 4.0, 8.0, 6.0]).view([2, 4]);
+        let out = OP_QUANTILE
+            .execute(&[Signal::DataFrame(Some(a)), Signal::Float(Some(0.5))])
+            .unwrap();
+        if let Signal::DataFrame(Some(res)) = out {
+            let expected =
+                Tensor::from_slice(&[2.5, 2.5, 2.5, 2.5, 6.0, 6.0, 6.0, 6.0]).view([2, 4]);
+            let is_all_true =
+                i64::try_from(res.isclose(&expected, 1e-5, 1e-8, false).all()).unwrap() != 0;
+            assert!(is_all_true);
+        } else {
+            panic!("Wrong output");
+        }
+    }
+
+    #[test]
+    fn test_quantile_out_of_range() {
+        let a = Tensor::from_slice(&[1.0, 2.0, 3.0, 5.0]).view([1, 4]);
+        let out = OP_QUANTILE
+            .execute(&[Signal::DataFrame(Some(a)), Signal::Float(Some(1.5))])
+            .unwrap();
+        if let Signal::DataFrame(Some(res)) = out {
+            assert!(i64::try_from(res.isnan().all()).unwrap() != 0);
+        } else {
+            panic!("Wrong output");
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use crate::{OperatorSpec, types::Signal};
+
+pub static OP_SCALE: OperatorSpec = OperatorSpec {
+    name: "scale",
+    inputs: &[Signal::DataFrame(None)],
+    output_shape: Signal::DataFrame(None),
+    execute: |args| match &args[0] {
+        Signal::DataFrame(Some(a)) => {
+            // Scale each row so that the gross exposure (sum of absolute values) is 1.
+            let gross =
+                a.abs()
+                    .nan_to_num(0.0, 0.0, 0.0)
+                    .sum_dim_intlist(Some(&[1][..]), true, a.kind());
+            Signal::DataFrame(Some(a / gross.clamp_min(1e-10)))
+        }
+        _ => panic!("scale expected DataFrame"),
+    },
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tch::Tensor;
+
+    #[test]
+    fn test_scale() {
+        let a = Tensor::from_slice(&[1.0, -3.0, f64::NAN, 2.0, 2.0, 0.0]).view([2, 3]);
+        let out = (OP_SCALE.execute)(&[Signal::DataFrame(Some(a))]);
+        if let Signal::DataFrame(Some(res)) = out {
+            let expected = Tensor::from_slice(&[0.25, -0.75, f64::NAN, 0.5, 0.5, 0.0]).view([2, 3]);
+            let is_all_true =
+                i64::try_from(res.isclose(&expected, 1e-5, 1e-8, true).all()).unwrap() != 0;
+            assert!(is_all_true);
+        } else {
+            panic!("Wrong output");
+        }
+        // Scalars have no exposure to scale.
+        assert!(OP_SCALE.execute(&[Signal::Float(Some(2.0))]).is_err());
+    }
+}
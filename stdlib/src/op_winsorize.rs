@@ -0,0 +1,75 @@
+use crate::{OperatorSpec, types::Signal};
+
+pub static OP_WINSORIZE: OperatorSpec = OperatorSpec {
+    name: "winsorize",
+    inputs: &[
+        Signal::DataFrame(None),
+        Signal::Float(None),
+        Signal::Float(None),
+    ],
+    output_shape: Signal::DataFrame(None),
+    execute: |args| match (&args[0], &args[1], &args[2]) {
+        (Signal::DataFrame(Some(a)), Signal::Float(Some(lower)), Signal::Float(Some(upper))) => {
+            // Bounds picked from a behavior's floats are not checked by the parser.
+            if !(0.0..=1.0).contains(lower) || !(0.0..=1.0).contains(upper) || lower >= upper {
+                let nan = tch::Tensor::full(a.size().as_slice(), f64::NAN, (a.kind(), a.device()));
+                return Signal::DataFrame(Some(nan));
+            }
+            // Clips each row to its own cross-sectional quantiles, ignoring NaN, so the bounds
+            // follow the data instead of being fixed values. NaN stays NaN.
+            let lo = a.nanquantile_scalar(*lower, Some(1), true, "linear");
+            let hi = a.nanquantile_scalar(*upper, Some(1), true, "linear");
+            Signal::DataFrame(Some(a.clamp_tensor(Some(&lo), Some(&hi))))
+        }
+        _ => panic!("winsorize expected DataFrame, Float and Float"),
+    },
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tch::Tensor;
+
+    #[test]
+    fn test_winsorize() {
+        let a = Tensor::from_slice(&[1.0, 2.0, 3.0, 4.0, 5.0, 50.0, f64::NAN, -10.0, 20.0, 30.0])
+            .view([2, 5]);
+        let out = OP_WINSORIZE
+            .execute(&[
+                Signal::DataFrame(Some(a)),
+                Signal::Float(Some(0.25)),
+                Signal::Float(Some(0.75)),
+            ])
+            .unwrap();
+        if let Signal::DataFrame(Some(res)) = out {
+            // Row quantiles: 2 and 4, then 12.5 and 35 over the four valid values.
+            let expected =
+                Tensor::from_slice(&[2.0, 2.0, 3.0, 4.0, 4.0, 35.0, f64::NAN, 12.5, 20.0, 30.0])
+                    .view([2, 5]);
+            let is_all_true =
+                i64::try_from(res.isclose(&expected, 1e-5, 1e-8, true).all()).unwrap() != 0;
+            assert!(is_all_true);
+        } else {
+            panic!("Wrong output");
+        }
+    }
+
+    #[test]
+    fn test_winsorize_invalid_bounds() {
+        for (lower, upper) in [(0.9, 0.1), (0.5, 0.5), (-0.1, 0.9), (0.1, 1.5)] {
+            let a = Tensor::from_slice(&[1.0, 2.0, 3.0, 4.0]).view([1, 4]);
+            let out = OP_WINSORIZE
+                .execute(&[
+                    Signal::DataFrame(Some(a)),
+                    Signal::Float(Some(lower)),
+                    Signal::Float(Some(upper)),
+                ])
+                .unwrap();
+            if let Signal::DataFrame(Some(res)) = out {
+                assert!(i64::try_from(res.isnan().all()).unwrap() != 0);
+            } else {
+                panic!("Wrong output");
+            }
+        }
+    }
+}